@@ -0,0 +1,153 @@
+use crate::{
+  types::{ExportFormat, Indent, SqlDialect},
+  utils::parse_row_range,
+};
+
+pub const HELP: &str = "\
+Usage: xltool [--no-config] [file]
+       xltool --merge <files...>
+       xltool [options] <file> --out FILE
+       xltool --batch [options] <files or globs...>
+
+Reads xlsx, xlsm, xlsb, xls, ods, csv and tsv files. Without a file, xltool
+offers recently opened files. Passing --out, more than one file (without
+--merge), or --batch exports without opening the TUI.
+
+Options:
+  --no-config         Ignore and don't update the saved config and recent files
+  --batch             Export headlessly, even for a single file
+  --merge             Open several files in one session to merge sheets across them
+  --sheet NAME        Sheet, named range or table to export (default: first sheet)
+  --first-row N       Header row index, as shown by the row trim step (default: 0),
+                      or a range such as 2-3 to join stacked header rows
+  --last-row N        Last row index to read, dropping totals or footers below it
+  --columns A,B       Export only these columns, named as in the header row
+  --out FILE          Write the export of a single file to FILE
+  --format FMT        json, jsonl, yaml, toml, sql, xml, html or csv
+                      (or xlsx, parquet and sqlite if built in)
+  --out-dir DIR       Directory to write exports into
+  --offset N          Skip the first N data rows
+  --limit N           Export at most N data rows
+  --indent 2|4|tab    JSON indentation
+  --dialect NAME      SQL dialect: sqlite, postgres or mysql (default: sqlite)
+  --compact           Write JSON on a single line
+  --typed             Write detected numbers and booleans as typed values and
+                      empty cells as null
+  --schema            Also write a JSON Schema of the records to FILE.schema.json
+  --bom               Start text output with a UTF-8 byte order mark
+  --crlf              Use CRLF line endings in text output
+  --strict            Fail a file when its validation rules fail
+  -h, --help          Show this help
+  -V, --version       Show the version
+
+Environment:
+  XLRS_ROWS_PER_PAGE  Preview rows per page (minimum 5)
+  XLRS_SHEET          Open this sheet straight away instead of the sheet list
+  XLRS_MAX_ROWS       Read at most this many rows of a sheet
+";
+
+#[derive(Default)]
+pub struct Cli {
+  pub files: Vec<String>,
+  pub use_config: bool,
+  pub batch: bool,
+  pub sheet: Option<String>,
+  pub first_row: Option<usize>,
+  pub header_rows: Option<usize>,
+  pub last_row: Option<usize>,
+  pub format: Option<ExportFormat>,
+  pub out_dir: Option<String>,
+  pub out: Option<String>,
+  pub columns: Vec<String>,
+  pub limit: Option<usize>,
+  pub offset: Option<usize>,
+  pub bom: bool,
+  pub crlf: bool,
+  pub indent: Option<Indent>,
+  pub dialect: Option<SqlDialect>,
+  pub compact: bool,
+  pub typed: bool,
+  pub schema: bool,
+  pub merge: bool,
+  pub strict: bool,
+  pub help: bool,
+  pub version: bool,
+}
+
+impl Cli {
+  pub fn parse(args: &[String]) -> Result<Self, String> {
+    let mut cli = Cli { use_config: true, ..Default::default() };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+      let mut value = || args.next().cloned().ok_or(format!("{} needs a value", arg));
+      match arg.as_str() {
+        "-h" | "--help" => cli.help = true,
+        "-V" | "--version" => cli.version = true,
+        "--no-config" => cli.use_config = false,
+        "--batch" => cli.batch = true,
+        "--bom" => cli.bom = true,
+        "--crlf" => cli.crlf = true,
+        "--compact" => cli.compact = true,
+        "--typed" => cli.typed = true,
+        "--schema" => cli.schema = true,
+        "--merge" => cli.merge = true,
+        "--indent" => {
+          let label = value()?;
+          let indent = Indent::from_label(&label);
+          cli.indent =
+            Some(indent.ok_or(format!("--indent expects 2, 4 or tab, got '{}'", label))?);
+        }
+        "--dialect" => {
+          let label = value()?;
+          let dialect = SqlDialect::from_label(&label);
+          cli.dialect = Some(
+            dialect
+              .ok_or(format!("--dialect expects sqlite, postgres or mysql, got '{}'", label))?,
+          );
+        }
+        "--strict" => cli.strict = true,
+        "--sheet" => cli.sheet = Some(value()?),
+        "--out-dir" => cli.out_dir = Some(value()?),
+        "--out" => cli.out = Some(value()?),
+        "--columns" => {
+          let names = value()?;
+          cli.columns = names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect();
+        }
+        "--first-row" => {
+          let rows = value()?;
+          let (first, last) = parse_row_range(&rows)
+            .ok_or(format!("{} expects a number or a range like 2-3, got '{}'", arg, rows))?;
+          cli.first_row = Some(first);
+          cli.header_rows = Some(last - first + 1);
+        }
+        "--last-row" => cli.last_row = Some(number(arg, &value()?)?),
+        "--limit" => cli.limit = Some(number(arg, &value()?)?),
+        "--offset" => cli.offset = Some(number(arg, &value()?)?),
+        "--format" => {
+          let name = value()?;
+          let format = ExportFormat::from_extension(&name);
+          cli.format = Some(format.ok_or(format!("Unknown format '{}'", name))?);
+        }
+        flag if flag.starts_with("--") => return Err(format!("Unknown flag {}", flag)),
+        pattern if pattern.contains(['*', '?', '[']) => {
+          let paths = glob::glob(pattern).map_err(|err| format!("{}: {}", pattern, err))?;
+          cli.files.extend(paths.flatten().map(|path| path.to_string_lossy().to_string()));
+        }
+        file => cli.files.push(file.to_string()),
+      }
+    }
+    Ok(cli)
+  }
+  pub fn headless(&self) -> bool {
+    self.batch || self.out.is_some() || (self.files.len() > 1 && !self.merge)
+  }
+}
+
+fn number(flag: &str, value: &str) -> Result<usize, String> {
+  value.parse().map_err(|_| format!("{} expects a number, got '{}'", flag, value))
+}
@@ -0,0 +1,135 @@
+use crate::types::{ColumnConfig, ColumnState, ExportFormat, Indent, SqlDialect};
+use serde::{Deserialize, Serialize};
+use std::{
+  env,
+  error::Error,
+  fs,
+  path::{Path, PathBuf},
+};
+
+const RECENT_FILES: usize = 10;
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+  pub deduplicate: bool,
+  pub format: ExportFormat,
+  pub toast_seconds: u64,
+  pub filename_template: String,
+  pub out_dir: String,
+  pub trim_values: bool,
+  pub typed_values: bool,
+  pub null_tokens: String,
+  pub bom: bool,
+  pub crlf: bool,
+  pub indent: Indent,
+  pub sql_dialect: SqlDialect,
+  pub compact: bool,
+  pub verbatim_keys: bool,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Self {
+      deduplicate: true,
+      format: ExportFormat::Json,
+      toast_seconds: 3,
+      filename_template: String::new(),
+      out_dir: String::new(),
+      trim_values: false,
+      typed_values: false,
+      null_tokens: String::new(),
+      bom: false,
+      crlf: false,
+      indent: Indent::Two,
+      sql_dialect: SqlDialect::Sqlite,
+      compact: false,
+      verbatim_keys: false,
+    }
+  }
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+  let base = env::var_os("XDG_CONFIG_HOME")
+    .map(PathBuf::from)
+    .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+  Some(base.join("xlrs"))
+}
+
+impl Config {
+  pub fn load() -> Self {
+    config_dir()
+      .and_then(|dir| fs::read_to_string(dir.join("config.toml")).ok())
+      .and_then(|text| toml::from_str(&text).ok())
+      .unwrap_or_default()
+  }
+  pub fn save(&self) -> Result<(), Box<dyn Error>> {
+    let dir = config_dir().ok_or("no config directory")?;
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("config.toml"), toml::to_string(self)?)?;
+    Ok(())
+  }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Profile {
+  pub sheet: String,
+  pub first_row: usize,
+  pub header_rows: usize,
+  pub header_separator: String,
+  #[serde(default)]
+  pub last_row: String,
+  #[serde(default)]
+  pub stop_at_empty: bool,
+  pub filter_column: String,
+  pub filter: String,
+  pub columns: Vec<ColumnProfile>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ColumnProfile {
+  pub name: String,
+  pub state: ColumnState,
+  #[serde(default)]
+  pub key: String,
+  #[serde(default)]
+  pub filter: String,
+  #[serde(default)]
+  pub config: ColumnConfig,
+}
+
+impl Profile {
+  pub fn path(workbook: &str) -> PathBuf {
+    let path = Path::new(workbook);
+    let stem = path.file_stem().map_or(workbook.into(), |stem| stem.to_string_lossy());
+    path.with_file_name(format!("{}.xlrs.toml", stem))
+  }
+  pub fn load(workbook: &str) -> Result<Self, Box<dyn Error>> {
+    Ok(toml::from_str(&fs::read_to_string(Self::path(workbook))?)?)
+  }
+  pub fn save(&self, workbook: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let path = Self::path(workbook);
+    fs::write(&path, toml::to_string(self)?)?;
+    Ok(path)
+  }
+}
+
+pub fn recent_files() -> Vec<String> {
+  config_dir()
+    .and_then(|dir| fs::read_to_string(dir.join("recent.txt")).ok())
+    .map(|text| text.lines().filter(|line| Path::new(line).exists()).map(str::to_string).collect())
+    .unwrap_or_default()
+}
+
+pub fn remember_file(path: &str) -> Result<(), Box<dyn Error>> {
+  let dir = config_dir().ok_or("no config directory")?;
+  let path = fs::canonicalize(path)?.to_string_lossy().to_string();
+  let mut recent = recent_files();
+  recent.retain(|file| *file != path);
+  recent.insert(0, path);
+  recent.truncate(RECENT_FILES);
+  fs::create_dir_all(&dir)?;
+  fs::write(dir.join("recent.txt"), recent.join("\n") + "\n")?;
+  Ok(())
+}
@@ -0,0 +1,745 @@
+use crate::{
+  types::{ColumnType, ExportFormat, SqlDialect},
+  utils::infer_type,
+};
+use serde::Serialize;
+use serde_json::{json, ser::PrettyFormatter, Map, Serializer, Value};
+use std::{collections::HashSet, error::Error};
+
+pub struct ExportOptions {
+  pub table: String,
+  pub root: String,
+  pub record: String,
+  pub indent: Option<&'static [u8]>,
+  pub dialect: SqlDialect,
+  pub columns: Vec<String>,
+}
+
+pub fn serialize(
+  format: ExportFormat,
+  records: &[Value],
+  options: &ExportOptions,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+  let text = match format {
+    ExportFormat::Json => format!("{}\n", to_json(records, options.indent)?),
+    ExportFormat::Ndjson => to_ndjson(records)?,
+    ExportFormat::Yaml => serde_yaml::to_string(records)?,
+    ExportFormat::Toml => to_toml(records)?,
+    ExportFormat::Sql => to_sql(records, options),
+    ExportFormat::Xml => to_xml(records, options),
+    ExportFormat::Html => to_html(records, &options.columns),
+    ExportFormat::Csv => to_csv(records, &options.columns)?,
+    #[cfg(feature = "parquet")]
+    ExportFormat::Parquet => return to_parquet(records, &options.columns),
+    #[cfg(feature = "sqlite")]
+    ExportFormat::Sqlite => return to_sqlite(records, &options.table, &options.columns),
+    #[cfg(feature = "xlsx")]
+    ExportFormat::Xlsx => return to_xlsx(records, &options.table, &options.columns),
+  };
+  Ok(text.into_bytes())
+}
+
+pub fn to_json<T: Serialize + ?Sized>(
+  value: &T,
+  indent: Option<&[u8]>,
+) -> Result<String, serde_json::Error> {
+  let mut out = Vec::new();
+  match indent {
+    Some(indent) => value
+      .serialize(&mut Serializer::with_formatter(&mut out, PrettyFormatter::with_indent(indent)))?,
+    None => value.serialize(&mut Serializer::new(&mut out))?,
+  }
+  Ok(String::from_utf8_lossy(&out).into_owned())
+}
+
+pub fn encode_text(bytes: Vec<u8>, bom: bool, crlf: bool) -> Vec<u8> {
+  let mut out = Vec::with_capacity(bytes.len() + 3);
+  if bom {
+    out.extend_from_slice("\u{feff}".as_bytes());
+  }
+  for byte in bytes {
+    if crlf && byte == b'\n' && out.last() != Some(&b'\r') {
+      out.push(b'\r');
+    }
+    out.push(byte);
+  }
+  out
+}
+
+pub fn parse(format: ExportFormat, text: &str) -> Option<Vec<Value>> {
+  let value = match format {
+    ExportFormat::Json => serde_json::from_str(text).ok()?,
+    ExportFormat::Ndjson => text
+      .lines()
+      .filter(|line| !line.trim().is_empty())
+      .map(serde_json::from_str::<Value>)
+      .collect::<Result<_, _>>()
+      .ok()?,
+    ExportFormat::Yaml => serde_yaml::from_str(text).ok()?,
+    ExportFormat::Toml => toml::from_str::<Value>(text).ok()?.get("records")?.clone(),
+    ExportFormat::Csv => from_csv(text)?,
+    _ => return None,
+  };
+  match value {
+    Value::Array(records) => Some(records),
+    _ => None,
+  }
+}
+
+pub fn to_matrix(records: &[Value], keys: &[String]) -> Vec<Value> {
+  let header = Value::from(keys.to_vec());
+  let rows = records.iter().map(|record| keys.iter().map(|key| record[key].clone()).collect());
+  std::iter::once(header).chain(rows).collect()
+}
+
+pub fn from_matrix(rows: Vec<Value>) -> Vec<Value> {
+  let Some(Value::Array(header)) = rows.first() else { return rows };
+  let keys: Vec<String> =
+    header.iter().map(|key| key.as_str().unwrap_or_default().to_string()).collect();
+  rows
+    .iter()
+    .skip(1)
+    .map(|row| {
+      let values = row.as_array().cloned().unwrap_or_default();
+      Value::Object(keys.iter().cloned().zip(values).collect())
+    })
+    .collect()
+}
+
+pub fn nest_keys(records: Vec<Value>, paths: &[(String, Vec<String>)]) -> Vec<Value> {
+  records.into_iter().map(|record| nest_record(record, paths)).collect()
+}
+
+fn nest_record(record: Value, paths: &[(String, Vec<String>)]) -> Value {
+  let Value::Object(mut map) = record else { return record };
+  for value in map.values_mut() {
+    if let Value::Array(items) = value {
+      *items = nest_keys(std::mem::take(items), paths);
+    }
+  }
+  for (key, path) in paths {
+    let Some(value) = map.remove(key) else { continue };
+    if let Err(value) = insert_path(&mut map, path, value) {
+      map.insert(key.clone(), value);
+    }
+  }
+  Value::Object(map)
+}
+
+fn insert_path(map: &mut Map<String, Value>, path: &[String], value: Value) -> Result<(), Value> {
+  let Some((first, rest)) = path.split_first() else { return Err(value) };
+  if rest.is_empty() {
+    if map.contains_key(first) {
+      return Err(value);
+    }
+    map.insert(first.clone(), value);
+    return Ok(());
+  }
+  match map.entry(first.clone()).or_insert_with(|| Value::Object(Map::new())) {
+    Value::Object(child) => insert_path(child, rest, value),
+    _ => Err(value),
+  }
+}
+
+// Required fields are paths from the record down to the property, so address.street is the
+// street inside address; array items are records of their own and start over at the top
+pub fn json_schema(records: &[Value], required: &HashSet<Vec<String>>, title: &str) -> Value {
+  let mut schema = value_schema(&[&Value::Array(records.to_vec())], required, &[]);
+  schema["$schema"] = json!("https://json-schema.org/draft/2020-12/schema");
+  schema["title"] = json!(title);
+  schema
+}
+
+fn value_schema(values: &[&Value], required_keys: &HashSet<Vec<String>>, path: &[String]) -> Value {
+  let mut types: Vec<&str> = Vec::new();
+  let mut properties: Vec<(&String, Vec<&Value>)> = Vec::new();
+  let mut items: Vec<&Value> = Vec::new();
+  let mut dates = true;
+  for value in values {
+    let kind = match value {
+      Value::Null => "null",
+      Value::Bool(_) => "boolean",
+      Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+      Value::Number(_) => "number",
+      Value::String(s) => {
+        dates &= chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").is_ok();
+        "string"
+      }
+      Value::Array(array) => {
+        items.extend(array);
+        "array"
+      }
+      Value::Object(map) => {
+        for (key, value) in map {
+          match properties.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, values)) => values.push(value),
+            None => properties.push((key, vec![value])),
+          }
+        }
+        "object"
+      }
+    };
+    if !types.contains(&kind) {
+      types.push(kind);
+    }
+  }
+  if types.contains(&"integer") && types.contains(&"number") {
+    types.retain(|&t| t != "integer");
+  }
+  let mut schema = match types.as_slice() {
+    [] => json!({}),
+    [kind] => json!({ "type": kind }),
+    kinds => json!({ "type": kinds }),
+  };
+  if types.contains(&"string") && dates {
+    schema["format"] = json!("date");
+  }
+  if types.contains(&"array") {
+    schema["items"] = value_schema(&items, required_keys, &[]);
+  }
+  if types.contains(&"object") {
+    let object_count = values.iter().filter(|v| v.is_object()).count();
+    let key_path = |key: &String| [path, std::slice::from_ref(key)].concat();
+    let required: Vec<&String> = properties
+      .iter()
+      .filter(|(key, values)| {
+        required_keys.contains(&key_path(key)) && values.len() == object_count
+      })
+      .map(|(key, _)| *key)
+      .collect();
+    let properties: Map<String, Value> = properties
+      .iter()
+      .map(|(key, values)| (key.to_string(), value_schema(values, required_keys, &key_path(key))))
+      .collect();
+    schema["properties"] = Value::Object(properties);
+    schema["required"] = json!(required);
+  }
+  schema
+}
+
+const SQL_BATCH: usize = 500;
+
+fn quote_ident(name: &str, dialect: SqlDialect) -> String {
+  match dialect {
+    SqlDialect::Mysql => format!("`{}`", name.replace('`', "``")),
+    _ => format!("\"{}\"", name.replace('"', "\"\"")),
+  }
+}
+
+fn sql_type(column_type: ColumnType, dialect: SqlDialect) -> &'static str {
+  match (column_type, dialect) {
+    (ColumnType::Int, SqlDialect::Sqlite) => "INTEGER",
+    (ColumnType::Int, _) => "BIGINT",
+    (ColumnType::Float, SqlDialect::Sqlite) => "REAL",
+    (ColumnType::Float, SqlDialect::Postgres) => "DOUBLE PRECISION",
+    (ColumnType::Float, SqlDialect::Mysql) => "DOUBLE",
+    (ColumnType::Bool, _) => "BOOLEAN",
+    (ColumnType::Date | ColumnType::String, _) => "TEXT",
+  }
+}
+
+fn sql_value(value: &Value, column_type: ColumnType, dialect: SqlDialect) -> String {
+  let quote = |s: &str| match dialect {
+    SqlDialect::Mysql => format!("'{}'", s.replace('\\', "\\\\").replace('\'', "''")),
+    _ => format!("'{}'", s.replace('\'', "''")),
+  };
+  match value {
+    Value::Null => "NULL".to_string(),
+    Value::String(s) if s.trim().is_empty() => "NULL".to_string(),
+    Value::String(s) => match column_type {
+      ColumnType::Int | ColumnType::Float => s.trim().to_string(),
+      ColumnType::Bool => s.trim().to_uppercase(),
+      ColumnType::Date | ColumnType::String => quote(s),
+    },
+    Value::Number(n) => n.to_string(),
+    Value::Bool(b) => if *b { "TRUE" } else { "FALSE" }.to_string(),
+    other => quote(&other.to_string()),
+  }
+}
+
+fn key_types(records: &[Value], keys: &[&String]) -> Vec<ColumnType> {
+  keys
+    .iter()
+    .map(|key| {
+      let cells: Vec<String> = records
+        .iter()
+        .filter_map(|record| match &record[key.as_str()] {
+          Value::Null => None,
+          Value::String(s) => Some(s.clone()),
+          other => Some(other.to_string()),
+        })
+        .collect();
+      infer_type(cells.iter().map(|s| s.as_str()))
+    })
+    .collect()
+}
+
+fn to_sql(records: &[Value], options: &ExportOptions) -> String {
+  let dialect = options.dialect;
+  let keys = record_keys(records, &options.columns);
+  let types = key_types(records, &keys);
+  let table = quote_ident(&options.table, dialect);
+  let columns: Vec<String> = keys.iter().map(|key| quote_ident(key, dialect)).collect();
+  let definitions: Vec<String> = columns
+    .iter()
+    .zip(&types)
+    .map(|(column, &column_type)| format!("  {} {}", column, sql_type(column_type, dialect)))
+    .collect();
+  let mut out = format!("CREATE TABLE {} (\n{}\n);\n", table, definitions.join(",\n"));
+  for batch in records.chunks(SQL_BATCH) {
+    let rows: Vec<String> = batch
+      .iter()
+      .map(|record| {
+        let values: Vec<String> = keys
+          .iter()
+          .zip(&types)
+          .map(|(key, &column_type)| sql_value(&record[key.as_str()], column_type, dialect))
+          .collect();
+        format!("  ({})", values.join(", "))
+      })
+      .collect();
+    out.push_str(&format!(
+      "INSERT INTO {} ({}) VALUES\n{};\n",
+      table,
+      columns.join(", "),
+      rows.join(",\n")
+    ));
+  }
+  out
+}
+
+fn to_ndjson(records: &[Value]) -> Result<String, serde_json::Error> {
+  let mut out = String::new();
+  for record in records {
+    out.push_str(&serde_json::to_string(record)?);
+    out.push('\n');
+  }
+  Ok(out)
+}
+
+// TOML has no null, so null fields are left out and null array items become empty strings
+fn without_nulls(value: &Value) -> Value {
+  match value {
+    Value::Object(map) => Value::Object(
+      map
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(key, value)| (key.clone(), without_nulls(value)))
+        .collect(),
+    ),
+    Value::Array(items) => Value::Array(
+      items
+        .iter()
+        .map(|item| if item.is_null() { json!("") } else { without_nulls(item) })
+        .collect(),
+    ),
+    value => value.clone(),
+  }
+}
+
+fn to_toml(records: &[Value]) -> Result<String, toml::ser::Error> {
+  let records: Vec<Value> = records.iter().map(without_nulls).collect();
+  toml::to_string(&json!({ "records": records }))
+}
+
+// With no records, every exported column is still a key, so tables and headers keep them
+fn record_keys<'a>(records: &'a [Value], columns: &'a [String]) -> Vec<&'a String> {
+  if records.is_empty() {
+    return columns.iter().collect();
+  }
+  let mut keys: Vec<&String> =
+    columns.iter().filter(|key| records.iter().any(|record| record.get(key).is_some())).collect();
+  for record in records {
+    let Value::Object(map) = record else { continue };
+    for key in map.keys() {
+      if !keys.contains(&key) {
+        keys.push(key);
+      }
+    }
+  }
+  keys
+}
+
+fn to_csv(records: &[Value], columns: &[String]) -> Result<String, Box<dyn Error>> {
+  let keys = record_keys(records, columns);
+  let mut writer = csv::Writer::from_writer(Vec::new());
+  writer.write_record(&keys)?;
+  for record in records {
+    writer.write_record(keys.iter().map(|key| match &record[key.as_str()] {
+      Value::Null => String::new(),
+      Value::String(s) => s.clone(),
+      other => other.to_string(),
+    }))?;
+  }
+  Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+fn from_csv(text: &str) -> Option<Value> {
+  let mut reader = csv::Reader::from_reader(text.as_bytes());
+  let keys: Vec<String> = reader.headers().ok()?.iter().map(str::to_string).collect();
+  let records = reader
+    .records()
+    .map(|row| {
+      let row = row.ok()?;
+      Some(Value::Object(keys.iter().cloned().zip(row.iter().map(Value::from)).collect()))
+    })
+    .collect::<Option<Vec<_>>>()?;
+  Some(Value::Array(records))
+}
+
+fn xml_escape(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+    .replace('\'', "&apos;")
+}
+
+fn is_xml_name(name: &str) -> bool {
+  let mut chars = name.chars();
+  chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+    && chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    && !name.to_lowercase().starts_with("xml")
+}
+
+fn xml_value(out: &mut String, value: &Value, record: &str, depth: usize) {
+  let indent = "  ".repeat(depth);
+  match value {
+    Value::Null => {}
+    Value::String(s) => out.push_str(&xml_escape(s)),
+    Value::Object(map) => {
+      out.push('\n');
+      for (key, value) in map {
+        let (open, close) = if is_xml_name(key) {
+          (key.clone(), key.as_str())
+        } else {
+          (format!("field name=\"{}\"", xml_escape(key)), "field")
+        };
+        out.push_str(&format!("{}  <{}>", indent, open));
+        xml_value(out, value, record, depth + 1);
+        out.push_str(&format!("</{}>\n", close));
+      }
+      out.push_str(&indent);
+    }
+    Value::Array(items) => {
+      out.push('\n');
+      for item in items {
+        out.push_str(&format!("{}  <{}>", indent, record));
+        xml_value(out, item, record, depth + 1);
+        out.push_str(&format!("</{}>\n", record));
+      }
+      out.push_str(&indent);
+    }
+    other => out.push_str(&xml_escape(&other.to_string())),
+  }
+}
+
+fn to_xml(records: &[Value], options: &ExportOptions) -> String {
+  let name = |name: &str, default: &str| {
+    if is_xml_name(name) {
+      name.to_string()
+    } else {
+      default.to_string()
+    }
+  };
+  let (root, record) = (name(&options.root, "records"), name(&options.record, "record"));
+  let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+  out.push_str(&format!("<{}>", root));
+  xml_value(&mut out, &Value::Array(records.to_vec()), &record, 0);
+  out.push_str(&format!("</{}>\n", root));
+  out
+}
+
+const HTML_CELL: &str = "border: 1px solid #d0d7de; padding: 4px 8px";
+
+fn to_html(records: &[Value], columns: &[String]) -> String {
+  let keys = record_keys(records, columns);
+  let types = key_types(records, &keys);
+  let mut out = String::from(
+    "<table style=\"border-collapse: collapse; font-family: sans-serif; font-size: 14px\">\n",
+  );
+  out.push_str("  <thead>\n    <tr>\n");
+  for key in &keys {
+    out.push_str(&format!(
+      "      <th style=\"{}; background: #f6f8fa; text-align: left\">{}</th>\n",
+      HTML_CELL,
+      xml_escape(key)
+    ));
+  }
+  out.push_str("    </tr>\n  </thead>\n  <tbody>\n");
+  for record in records {
+    out.push_str("    <tr>\n");
+    for (key, column_type) in keys.iter().zip(&types) {
+      let text = match &record[key.as_str()] {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+      };
+      let align = match column_type {
+        ColumnType::Int | ColumnType::Float => "; text-align: right",
+        _ => "",
+      };
+      out.push_str(&format!(
+        "      <td style=\"{}{}\">{}</td>\n",
+        HTML_CELL,
+        align,
+        xml_escape(&text).replace('\n', "<br>")
+      ));
+    }
+    out.push_str("    </tr>\n");
+  }
+  out.push_str("  </tbody>\n</table>\n");
+  out
+}
+
+#[cfg(feature = "parquet")]
+fn to_parquet(records: &[Value], columns: &[String]) -> Result<Vec<u8>, Box<dyn Error>> {
+  use crate::utils::parse_date;
+  use parquet::{
+    basic::{LogicalType, Repetition, TimeUnit, Type as PhysicalType},
+    data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    format::MilliSeconds,
+    schema::types::Type,
+  };
+  use std::sync::Arc;
+
+  let columns: Vec<(&String, Vec<Option<String>>)> = record_keys(records, columns)
+    .into_iter()
+    .map(|key| {
+      let cells = records
+        .iter()
+        .map(|record| match record.get(key) {
+          None | Some(Value::Null) => None,
+          Some(Value::String(s)) if s.is_empty() => None,
+          Some(Value::String(s)) => Some(s.clone()),
+          Some(other) => Some(other.to_string()),
+        })
+        .collect();
+      (key, cells)
+    })
+    .collect();
+  let types: Vec<ColumnType> = columns
+    .iter()
+    .map(|(_, cells)| infer_type(cells.iter().flatten().map(|s| s.as_str())))
+    .collect();
+  let fields = columns
+    .iter()
+    .zip(&types)
+    .map(|((key, _), column_type)| {
+      let builder = match column_type {
+        ColumnType::Bool => Type::primitive_type_builder(key, PhysicalType::BOOLEAN),
+        ColumnType::Int => Type::primitive_type_builder(key, PhysicalType::INT64),
+        ColumnType::Float => Type::primitive_type_builder(key, PhysicalType::DOUBLE),
+        ColumnType::Date => Type::primitive_type_builder(key, PhysicalType::INT64)
+          .with_logical_type(Some(LogicalType::Timestamp {
+            is_adjusted_to_u_t_c: false,
+            unit: TimeUnit::MILLIS(MilliSeconds {}),
+          })),
+        ColumnType::String => Type::primitive_type_builder(key, PhysicalType::BYTE_ARRAY)
+          .with_logical_type(Some(LogicalType::String)),
+      };
+      Ok(Arc::new(builder.with_repetition(Repetition::OPTIONAL).build()?))
+    })
+    .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+  let schema = Arc::new(Type::group_type_builder("schema").with_fields(fields).build()?);
+  let mut buffer = Vec::new();
+  let mut writer =
+    SerializedFileWriter::new(&mut buffer, schema, Arc::new(WriterProperties::builder().build()))?;
+  let mut row_group = writer.next_row_group()?;
+  for ((_, cells), column_type) in columns.iter().zip(&types) {
+    let Some(mut column) = row_group.next_column()? else { break };
+    let levels: Vec<i16> = cells.iter().map(|cell| cell.is_some() as i16).collect();
+    let present = cells.iter().flatten().map(|s| s.trim());
+    match column_type {
+      ColumnType::Bool => {
+        let values: Vec<bool> = present.map(|s| s.eq_ignore_ascii_case("true")).collect();
+        column.typed::<BoolType>().write_batch(&values, Some(&levels), None)?;
+      }
+      ColumnType::Int => {
+        let values: Vec<i64> = present.map(|s| s.parse().unwrap_or_default()).collect();
+        column.typed::<Int64Type>().write_batch(&values, Some(&levels), None)?;
+      }
+      ColumnType::Float => {
+        let values: Vec<f64> = present.map(|s| s.parse().unwrap_or_default()).collect();
+        column.typed::<DoubleType>().write_batch(&values, Some(&levels), None)?;
+      }
+      ColumnType::Date => {
+        let values: Vec<i64> = present
+          .map(|s| parse_date(s).map_or(0, |date| date.and_utc().timestamp_millis()))
+          .collect();
+        column.typed::<Int64Type>().write_batch(&values, Some(&levels), None)?;
+      }
+      ColumnType::String => {
+        let values: Vec<ByteArray> =
+          cells.iter().flatten().map(|s| ByteArray::from(s.as_str())).collect();
+        column.typed::<ByteArrayType>().write_batch(&values, Some(&levels), None)?;
+      }
+    }
+    column.close()?;
+  }
+  row_group.close()?;
+  writer.close()?;
+  Ok(buffer)
+}
+
+#[cfg(feature = "sqlite")]
+fn to_sqlite(
+  records: &[Value],
+  table: &str,
+  columns: &[String],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+  use rusqlite::{params_from_iter, types::Value as SqlValue, Connection, DatabaseName};
+
+  let keys = record_keys(records, columns);
+  if keys.is_empty() {
+    return Err("No columns to export".into());
+  }
+  let types = key_types(records, &keys);
+  let definitions: Vec<String> = keys
+    .iter()
+    .zip(&types)
+    .map(|(key, &column_type)| {
+      let sql_type = match column_type {
+        ColumnType::Bool => "INTEGER",
+        column_type => sql_type(column_type, SqlDialect::Sqlite),
+      };
+      format!("{} {}", quote_ident(key, SqlDialect::Sqlite), sql_type)
+    })
+    .collect();
+  let connection = Connection::open_in_memory()?;
+  let table = quote_ident(table, SqlDialect::Sqlite);
+  connection.execute(&format!("CREATE TABLE {} ({})", table, definitions.join(", ")), [])?;
+  let transaction = connection.unchecked_transaction()?;
+  {
+    let placeholders = vec!["?"; keys.len()].join(", ");
+    let mut insert =
+      transaction.prepare(&format!("INSERT INTO {} VALUES ({})", table, placeholders))?;
+    for record in records {
+      let values = keys.iter().zip(&types).map(|(key, column_type)| match &record[key.as_str()] {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(*b as i64),
+        Value::Number(n) => {
+          n.as_i64().map_or(SqlValue::Real(n.as_f64().unwrap_or_default()), SqlValue::Integer)
+        }
+        Value::String(s) if s.is_empty() && *column_type != ColumnType::String => SqlValue::Null,
+        Value::String(s) if *column_type == ColumnType::Bool => {
+          SqlValue::Integer(s.trim().eq_ignore_ascii_case("true") as i64)
+        }
+        Value::String(s) => SqlValue::Text(s.clone()),
+        other => SqlValue::Text(other.to_string()),
+      });
+      insert.execute(params_from_iter(values))?;
+    }
+  }
+  transaction.commit()?;
+  Ok(connection.serialize(DatabaseName::Main)?.to_vec())
+}
+
+#[cfg(feature = "xlsx")]
+fn to_xlsx(records: &[Value], sheet: &str, columns: &[String]) -> Result<Vec<u8>, Box<dyn Error>> {
+  use rust_xlsxwriter::{Format, Workbook};
+
+  let keys = record_keys(records, columns);
+  let types = key_types(records, &keys);
+  let mut workbook = Workbook::new();
+  let worksheet = workbook.add_worksheet();
+  // Excel sheet names are at most 31 characters and can't contain []:*?/\
+  let name: String = sheet.chars().filter(|c| !"[]:*?/\\".contains(*c)).take(31).collect();
+  if !name.trim().is_empty() {
+    worksheet.set_name(name.trim_matches('\''))?;
+  }
+  let bold = Format::new().set_bold();
+  for (col, key) in keys.iter().enumerate() {
+    worksheet.write_string_with_format(0, col as u16, key.as_str(), &bold)?;
+  }
+  for (row, record) in records.iter().enumerate() {
+    let row = row as u32 + 1;
+    for (col, (key, column_type)) in keys.iter().zip(&types).enumerate() {
+      let col = col as u16;
+      match &record[key.as_str()] {
+        Value::Null => {}
+        Value::Bool(b) => {
+          worksheet.write_boolean(row, col, *b)?;
+        }
+        Value::Number(n) => {
+          worksheet.write_number(row, col, n.as_f64().unwrap_or_default())?;
+        }
+        Value::String(s) if s.trim().is_empty() => {}
+        Value::String(s) => match column_type {
+          ColumnType::Int | ColumnType::Float => {
+            worksheet.write_number(row, col, s.trim().parse::<f64>()?)?;
+          }
+          ColumnType::Bool => {
+            worksheet.write_boolean(row, col, s.trim().eq_ignore_ascii_case("true"))?;
+          }
+          ColumnType::Date | ColumnType::String => {
+            worksheet.write_string(row, col, s.as_str())?;
+          }
+        },
+        other => {
+          worksheet.write_string(row, col, other.to_string())?;
+        }
+      }
+    }
+  }
+  worksheet.set_freeze_panes(1, 0)?;
+  Ok(workbook.save_to_buffer()?)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn options() -> ExportOptions {
+    ExportOptions {
+      table: "data".into(),
+      root: "records".into(),
+      record: "record".into(),
+      indent: None,
+      dialect: SqlDialect::default(),
+      columns: Vec::new(),
+    }
+  }
+
+  #[test]
+  fn toml_skips_null_cells() {
+    let records = vec![json!({ "id": 1, "name": null, "tags": ["a", null] })];
+    let bytes = serialize(ExportFormat::Toml, &records, &options()).unwrap();
+    let text = String::from_utf8(bytes).unwrap();
+    assert!(!text.contains("name"));
+    let parsed = parse(ExportFormat::Toml, &text).unwrap();
+    assert_eq!(parsed, vec![json!({ "id": 1, "tags": ["a", ""] })]);
+  }
+
+  #[test]
+  fn schema_requires_nested_fields_by_path() {
+    let records =
+      vec![json!({ "street": "x", "address": { "street": "Main St", "city": "Oslo" } })];
+    let required = HashSet::from([vec!["address".to_string(), "street".to_string()]]);
+    let schema = json_schema(&records, &required, "t");
+    assert_eq!(schema["items"]["required"], json!([]));
+    assert_eq!(schema["items"]["properties"]["address"]["required"], json!(["street"]));
+    let schema = json_schema(&records, &HashSet::from([vec!["street".to_string()]]), "t");
+    assert_eq!(schema["items"]["required"], json!(["street"]));
+    assert_eq!(schema["items"]["properties"]["address"]["required"], json!([]));
+  }
+
+  #[test]
+  fn sql_without_records_still_defines_columns() {
+    let options = ExportOptions { columns: vec!["id".into(), "name".into()], ..options() };
+    let text = String::from_utf8(serialize(ExportFormat::Sql, &[], &options).unwrap()).unwrap();
+    assert_eq!(text, "CREATE TABLE \"data\" (\n  \"id\" TEXT,\n  \"name\" TEXT\n);\n");
+  }
+
+  #[cfg(feature = "sqlite")]
+  #[test]
+  fn sqlite_without_records_creates_the_table() {
+    let options = ExportOptions { columns: vec!["id".into()], ..options() };
+    let bytes = serialize(ExportFormat::Sqlite, &[], &options).unwrap();
+    assert!(bytes.starts_with(b"SQLite format 3"));
+    let options = ExportOptions { columns: Vec::new(), ..options };
+    assert!(serialize(ExportFormat::Sqlite, &[], &options).is_err());
+  }
+}
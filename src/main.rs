@@ -1,94 +1,316 @@
+mod cli;
+mod config;
+mod export;
+mod source;
 mod types;
 mod utils;
-use calamine::{open_workbook, Reader, Xlsx};
 use crossterm::{
   event::{
-    self, poll,
-    Event::Key,
-    KeyCode::{Char, Down, End, Enter, Home, Left, Right, Tab, Up},
-    KeyEvent, KeyModifiers,
+    self, poll, DisableMouseCapture, EnableMouseCapture,
+    Event::{Key, Mouse},
+    KeyCode::{Char, Down, End, Enter, Esc, Home, Left, PageDown, PageUp, Right, Tab, Up},
+    KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
   },
   execute,
   terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
 use ratatui::{
   backend::CrosstermBackend,
-  layout::{Alignment, Constraint, Direction::Vertical, Layout},
+  layout::{Alignment, Constraint, Direction::Vertical, Layout, Rect},
   style::{Color, Style},
   text::{Line, Span},
-  widgets::{List, ListItem, Paragraph},
+  widgets::{Block, Borders, Clear, Gauge, List, ListItem, Paragraph, Wrap},
   Frame, Terminal,
 };
-use serde_json::to_string_pretty;
 use std::{
+  borrow::Cow,
   collections::{HashMap, HashSet},
   env::args,
   error::Error,
-  fs::File,
-  io::{stdout, Read, Seek, Write},
+  fs::{self, File},
+  io::{stdout, Stdout, Write},
+  panic,
+  path::Path,
+  process,
   time::{Duration, Instant},
 };
 use tui_input::{backend::crossterm::EventHandler, Input};
 
-use types::{App, ColumnConfig, ColumnState, ExportEdit, Step, FOCUSED_STYLE};
-use utils::{create_table, layout, navigate_index, normalize_text, visual_width};
+use cli::Cli;
+use config::{ColumnProfile, Config, Profile};
+use export::ExportOptions;
+use source::Source;
+use types::{
+  AffixWhen, App, ColumnConfig, ColumnFilter, ColumnState, ColumnType, ExportEdit, ExportFormat,
+  JoinState, MergeSheet, NumberFormat, NumberStats, Popup, RowTrimEdit, Step, FOCUSED_STYLE,
+  MIN_ROWS_PER_PAGE, TEMPLATE_PLACEHOLDERS,
+};
+use utils::{
+  centered_rect, compare_cells, create_table, display_cell, follow_scroll, group_ordered,
+  header_cells, infer_type, join_header_rows, layout, navigate_index, normalize_text, pair_columns,
+  parse_number, safe_filename, table_columns, table_state, visual_width,
+};
+
+const HEADER_SCAN_ROWS: usize = 20;
+const MERGE_EDIT_DISTANCE: usize = 2;
+const PREVIEW_RECORDS: usize = 5;
+const STATS_FREQUENT: usize = 10;
+const COLUMN_PAGE: usize = 10;
+const SHEET_COLUMN: &str = "_sheet";
 
 impl App {
-  fn load_sheet<T: Read + Seek>(&mut self, xlsx: &mut Xlsx<T>) -> bool {
-    if let Some(idx) = self.selected_sheet {
-      if let Ok(range) = xlsx.worksheet_range(&self.sheets[idx]) {
-        self.data =
-          range.rows().map(|row| row.iter().map(|cell| cell.to_string()).collect()).collect();
-        if self.data.is_empty() {
-          return false;
-        }
-        let col_count = self.data.get(0).map_or(0, |r| r.len());
-        self.columns = vec![ColumnState::Hidden; col_count];
-        self.column_configs = vec![ColumnConfig::default(); col_count];
-        self.custom_keys = vec![Input::default(); col_count];
-        self.export_filename = {
-          let default = self.get_default_filename();
-          Input::default().with_value(default)
-        };
-        return true;
-      }
+  fn load_sheet(&mut self, source: &mut Source) -> bool {
+    self.load_sheet_with_progress(source, &mut |_, _| {})
+  }
+  fn load_sheet_with_progress(
+    &mut self,
+    source: &mut Source,
+    progress: &mut dyn FnMut(usize, usize),
+  ) -> bool {
+    let Some(idx) = self.selected_sheet else { return false };
+    // One extra row tells a sheet that was cut at the limit from one that just fits
+    let limit = self.max_rows.map(|rows| rows.saturating_add(1));
+    let Some(mut rows) = source.rows_with_progress(&self.sheets[idx], limit, progress) else {
+      self.toast(format!("Could not read sheet '{}'", self.sheets[idx]));
+      return false;
+    };
+    if let Some(max_rows) = self.max_rows.filter(|&max_rows| rows.len() > max_rows) {
+      rows.truncate(max_rows);
+      self.toast(format!(
+        "Sheet '{}' has more than {} rows, read only the first {} (XLRS_MAX_ROWS)",
+        self.sheets[idx], max_rows, max_rows
+      ));
     }
-    false
+    self.data = rows;
+    self.header_backup = None;
+    self.trimmed_rows.clear();
+    self.merged_from.clear();
+    self.workspace_undo.clear();
+    self.workspace_redo.clear();
+    if self.data.is_empty() {
+      self.toast(format!("Sheet '{}' is empty", self.sheets[idx]));
+      return false;
+    }
+    let col_count = self.data.iter().map(|r| r.len()).max().unwrap_or(0);
+    for row in self.data.iter_mut() {
+      row.resize(col_count, String::new());
+    }
+    self.reset_columns(col_count);
+    self.export_filename = {
+      let default = self.get_default_filename();
+      Input::default().with_value(default)
+    };
+    self.filename_edited = false;
+    true
+  }
+  fn reset_columns(&mut self, col_count: usize) {
+    self.columns = vec![ColumnState::Hidden; col_count];
+    self.column_configs = vec![ColumnConfig::default(); col_count];
+    self.custom_keys = vec![Input::default(); col_count];
+    self.column_filters = vec![Input::default(); col_count];
+    self.sort = None;
+    self.marked_rows.clear();
+    self.undo_stack.clear();
+    self.redo_stack.clear();
   }
-  fn is_row_visible(&self, row: &[String]) -> bool {
-    row
+  fn is_row_visible(&self, row: &[String], filters: &[(usize, ColumnFilter)]) -> bool {
+    if !filters.iter().all(|(col_idx, filter)| filter.matches(row.get(*col_idx).map_or("", |s| s)))
+    {
+      return false;
+    }
+    if let Some(col_idx) = self.row_filter_column {
+      let needle = self.row_filter.value().to_lowercase();
+      let cell = row.get(col_idx).map(|s| s.to_lowercase()).unwrap_or_default();
+      if !needle.is_empty() && !cell.contains(&needle) {
+        return false;
+      }
+    }
+    let mut filled = row
       .iter()
       .enumerate()
-      .filter(|(i, _)| matches!(self.columns[*i], ColumnState::Original | ColumnState::NonEmpty))
-      .all(|(i, cell)| matches!(self.columns[i], ColumnState::Original) || !cell.trim().is_empty())
+      .filter(|(i, _)| self.columns[*i] == ColumnState::NonEmpty)
+      .map(|(_, cell)| !cell.trim().is_empty())
+      .peekable();
+    if self.any_non_empty {
+      filled.peek().is_none() || filled.any(|filled| filled)
+    } else {
+      filled.all(|filled| filled)
+    }
   }
-  fn handle_key(
-    &mut self,
-    key: KeyEvent,
-    modifiers: KeyModifiers,
-    xlsx: &mut Xlsx<impl Read + Seek>,
-  ) -> bool {
+  fn handle_key(&mut self, key: KeyEvent, modifiers: KeyModifiers, source: &mut Source) -> bool {
     if modifiers.contains(KeyModifiers::CONTROL) {
       match key.code {
         Char('q') => return true,
         Char('b') => {
-          self.step = self.handle_back();
+          self.go_back();
+        }
+        Char('p') if self.step == Step::Export && self.popup.is_none() => {
+          self.show_records_preview();
+          return false;
+        }
+        Char('p') if self.popup == Some(Popup::Records) => {
+          self.popup = None;
+          return false;
+        }
+        Char('c') if self.step == Step::Export && self.popup.is_none() => {
+          self.copy_export();
+          return false;
+        }
+        Char('r') if self.step == Step::Export && self.popup.is_none() => {
+          self.popup_text =
+            "Keys, column settings, deduplicate, format and filename go back to their defaults.\n\
+             Column visibility and the header row are kept."
+              .to_string();
+          self.popup_changed.clear();
+          self.popup_scroll = 0;
+          self.popup = Some(Popup::ConfirmReset);
+          return false;
+        }
+        Char('s') if self.popup.is_none() && !self.data.is_empty() => {
+          self.save_profile();
+          return false;
+        }
+        Char('l') if self.popup.is_none() => {
+          self.load_profile(source);
+          return false;
+        }
+        Char('u') if self.popup.is_none() => {
+          self.clear_focused_input();
+          return false;
+        }
+        Char('z') if self.undo_stack.is_empty() && !self.workspace_undo.is_empty() => {
+          self.undo_workspace();
+          return false;
+        }
+        Char('z') => {
+          self.undo();
+          return false;
+        }
+        Char('y') if self.redo_stack.is_empty() && !self.workspace_redo.is_empty() => {
+          self.redo_workspace();
+          return false;
+        }
+        Char('y') => {
+          self.redo();
+          return false;
+        }
+        _ => {}
+      }
+    }
+    if let Some(popup @ (Popup::Confirm | Popup::ConfirmReset)) = self.popup {
+      match key.code {
+        Enter => {
+          self.popup = None;
+          if popup == Popup::Confirm {
+            self.pending_export = true;
+          } else {
+            self.reset_export();
+          }
+        }
+        Esc => self.popup = None,
+        _ => {}
+      }
+      return false;
+    }
+    if self.popup.is_some() {
+      match key.code {
+        Char('v') | Char('i') | Esc | Enter => self.popup = None,
+        Up | Char('k') => self.popup_scroll = self.popup_scroll.saturating_sub(1),
+        // The cell popup wraps its text, so it clamps the scroll when drawn
+        Down | Char('j') if self.popup == Some(Popup::Cell) => self.popup_scroll += 1,
+        Down | Char('j') => {
+          let last_line = self.popup_text.lines().count().saturating_sub(1) as u16;
+          self.popup_scroll = (self.popup_scroll + 1).min(last_line);
         }
         _ => {}
       }
+      return false;
+    }
+    if key.code == Esc
+      && !self.editing_filter
+      && !self.editing_column_filter
+      && !self.editing_search
+    {
+      match self.step {
+        Step::SheetSelect if self.sheet_search.value().is_empty() => return true,
+        Step::SheetSelect => {
+          self.sheet_search = Input::default();
+          self.update_sheet_search();
+        }
+        _ => self.go_back(),
+      }
+      return false;
     }
     match self.step {
-      Step::SheetSelect => self.handle_sheet_select(key, xlsx),
-      Step::RowTrim => self.handle_row_trim(key, xlsx),
-      Step::MergePrompt => self.handle_merge_prompt(key, xlsx),
-      Step::ColSelect => self.handle_col_select(key),
-      Step::Preview => self.handle_preview(key),
-      Step::Export => self.handle_export(key),
+      Step::SheetSelect => self.handle_sheet_select(key),
+      Step::RowTrim => self.handle_row_trim(key, source),
+      Step::MergePrompt => self.handle_merge_prompt(key, source),
+      Step::Join => self.handle_join(key, source),
+      Step::ColSelect if key.code == Char('J') => self.start_join(source),
+      Step::Preview if key.code == Char('g') && !self.editing_preview() => self.summarize(),
+      Step::Preview if key.code == Char('v') && !self.editing_preview() => {
+        self.inspect_cell(source)
+      }
+      Step::ColSelect | Step::Preview | Step::Export => {
+        let before = self.snapshot();
+        match self.step {
+          Step::ColSelect => self.handle_col_select(key),
+          Step::Preview => self.handle_preview(key),
+          _ => self.handle_export(key),
+        }
+        self.record_history(before);
+      }
     }
     false
   }
-  fn handle_sheet_select<T: Read + Seek>(&mut self, key: KeyEvent, xlsx: &mut Xlsx<T>) {
+  fn clear_focused_input(&mut self) {
+    match self.step {
+      Step::SheetSelect => {
+        self.sheet_search.reset();
+        self.update_sheet_search();
+      }
+      Step::RowTrim => match self.row_trim_edit {
+        RowTrimEdit::FirstRow => self.row_input.reset(),
+        RowTrimEdit::LastRow => self.last_row_input.reset(),
+        RowTrimEdit::Separator => self.header_separator.reset(),
+      },
+      Step::Preview if self.editing_search => self.data_search.reset(),
+      Step::Preview if self.editing_column_filter => {
+        if let Some(&col_idx) = self.visible_columns().get(self.selected_column) {
+          self.column_filters[col_idx].reset();
+        }
+        self.current_page = 0;
+        self.selected_row = 0;
+      }
+      Step::Preview if self.editing_filter => {
+        self.row_filter.reset();
+        self.current_page = 0;
+        self.selected_row = 0;
+      }
+      Step::Export => {
+        let before = self.snapshot();
+        if let Some(target) = self.get_export_target() {
+          target.reset();
+        }
+        self.record_history(before);
+      }
+      _ => {}
+    }
+  }
+  fn handle_sheet_select(&mut self, key: KeyEvent) {
     match key.code {
+      Up | Down if !self.sheet_search.value().is_empty() => {
+        let pos = self.matching_sheets.iter().position(|&i| Some(i) == self.selected_sheet);
+        let pos = match (key.code, pos) {
+          (Up, Some(p)) => p.saturating_sub(1),
+          (Down, Some(p)) => (p + 1).min(self.matching_sheets.len().saturating_sub(1)),
+          _ => 0,
+        };
+        if let Some(&i) = self.matching_sheets.get(pos) {
+          self.selected_sheet = Some(i);
+        }
+      }
       Up => {
         self.selected_sheet = self.selected_sheet.map(|i| i.saturating_sub(1)).or(Some(0));
       }
@@ -96,10 +318,8 @@ impl App {
         self.selected_sheet =
           self.selected_sheet.map(|i| (i + 1).min(self.sheets.len() - 1)).or(Some(0));
       }
-      Enter => {
-        if self.load_sheet(xlsx) {
-          self.step = Step::RowTrim;
-        }
+      Enter if self.selected_sheet.is_some() => {
+        self.pending_load = true;
       }
       _ => {
         self.sheet_search.handle_event(&Key(key));
@@ -107,73 +327,370 @@ impl App {
       }
     }
   }
-  fn handle_row_trim<T: Read + Seek>(&mut self, key: KeyEvent, xlsx: &mut Xlsx<T>) {
+  fn go_back(&mut self) {
+    if self.step == Step::ColSelect && !self.workspace_undo.is_empty() {
+      self.undo_workspace();
+      return;
+    }
+    self.step = self.handle_back();
+    if self.step == Step::RowTrim {
+      self.restore_header();
+    }
+  }
+  // The header of another sheet ending at header_row, joined like the primary header
+  fn sheet_header(&self, rows: &[Vec<String>], header_row: usize) -> Vec<String> {
+    let first = (header_row + 1).saturating_sub(self.header_rows);
+    match rows.get(first..=header_row) {
+      Some(header) => join_header_rows(header, self.header_separator.value()),
+      None => Vec::new(),
+    }
+  }
+  fn restore_header(&mut self) {
+    if let Some((row, original)) = self.header_backup.take() {
+      self.data[row] = original;
+    }
+    self.data.append(&mut self.trimmed_rows);
+  }
+  fn combine_header(&mut self, row: usize) -> usize {
+    let last = row + self.header_rows - 1;
+    if self.header_rows > 1 {
+      let header = join_header_rows(&self.data[row..=last], self.header_separator.value());
+      let original = std::mem::replace(&mut self.data[last], header);
+      self.header_backup = Some((last, original));
+    }
+    last
+  }
+  fn handle_row_trim(&mut self, key: KeyEvent, source: &mut Source) {
     match key.code {
+      Tab => self.row_trim_edit = self.row_trim_edit.next(),
+      Char('e') if self.row_trim_edit != RowTrimEdit::Separator => self.stop_at_empty ^= true,
+      Up => self.header_rows = (self.header_rows + 1).min(self.data.len().max(1)),
+      Down => self.header_rows = self.header_rows.saturating_sub(1).max(1),
       Enter => {
-        if let Ok(row) = self.row_input.value().trim().parse::<usize>() {
-          if row < self.data.len() {
-            self.first_row = row;
-            let merge_info = self.check_merge_options(xlsx);
+        self.restore_header();
+        if let Some((row, header_rows)) = self.header_range() {
+          self.header_rows = header_rows;
+          if row + self.header_rows <= self.data.len() {
+            if let Err(err) = self.trim_footer(row + self.header_rows - 1) {
+              self.toast(err);
+              return;
+            }
+            self.first_row = self.combine_header(row);
+            self.update_empty_columns();
+            let merge_info = self.check_merge_options(source);
             if !merge_info.is_empty() {
               self.merge_info = Some(merge_info);
+              self.merge_selected = 0;
+              self.merge_focus = 0;
+              self.merge_dropped.clear();
               self.step = Step::MergePrompt;
             } else {
               self.step = Step::ColSelect;
+              self.warn_if_no_data();
             }
           }
         }
       }
       _ => {
-        self.row_input.handle_event(&Key(key));
+        let input = match self.row_trim_edit {
+          RowTrimEdit::FirstRow => &mut self.row_input,
+          RowTrimEdit::LastRow => &mut self.last_row_input,
+          RowTrimEdit::Separator => &mut self.header_separator,
+        };
+        input.handle_event(&Key(key));
       }
     }
   }
-  fn handle_merge_prompt<T: Read + Seek>(&mut self, key: KeyEvent, xlsx: &mut Xlsx<T>) {
+  // Sets aside the rows past the last row, such as totals and footers
+  fn trim_footer(&mut self, header_row: usize) -> Result<(), String> {
+    let end = self.data_end(header_row)?;
+    self.trimmed_rows = self.data.split_off(end);
+    Ok(())
+  }
+  fn warn_if_no_data(&mut self) {
+    if self.first_row + 1 >= self.data.len() {
+      let name = self.selected_sheet.map_or("", |idx| self.sheets[idx].as_str());
+      self.toast(format!("Sheet '{}' has a header but no data rows", name));
+    }
+  }
+  fn handle_merge_prompt(&mut self, key: KeyEvent, source: &mut Source) {
     match key.code {
+      Char('y') | Char('Y')
+        if self.merge_columns().iter().all(|c| self.merge_dropped.contains(c)) =>
+      {
+        self.toast("No columns left to merge · u keeps all columns".to_string());
+      }
       Char('y') | Char('Y') => {
-        self.perform_merge(xlsx);
+        self.perform_merge(source);
+        self.update_empty_columns();
         self.merge_info = None;
         self.step = Step::ColSelect;
+        self.warn_if_no_data();
       }
       Char('n') | Char('N') => {
         self.merge_info = None;
         self.step = Step::ColSelect;
+        self.warn_if_no_data();
+      }
+      Up | Down => {
+        let len = self.merge_info.as_ref().map_or(0, |info| info.len()) + self.merge_rows().len();
+        self.merge_selected = navigate_index(self.merge_selected, len, key.code);
+      }
+      Char('f') | Char('F') => {
+        self.merge_fuzzy ^= true;
+        let Some(primary_header) = self.data.get(self.first_row) else { return };
+        let distance = self.merge_distance();
+        for sheet in self.merge_info.iter_mut().flatten() {
+          sheet.pairs = pair_columns(primary_header, &sheet.header, distance);
+        }
+        self.toast(if self.merge_fuzzy {
+          format!("Also pairing names up to {} edits apart", MERGE_EDIT_DISTANCE)
+        } else {
+          "Pairing exact and normalized names only".to_string()
+        });
+      }
+      Char('u') | Char('U') => {
+        self.merge_union ^= true;
+        let len = self.merge_info.as_ref().map_or(0, |info| info.len()) + self.merge_rows().len();
+        self.merge_selected = self.merge_selected.min(len.saturating_sub(1));
+      }
+      Char('s') | Char('S') => self.merge_provenance ^= true,
+      Tab => {
+        let included = self.merge_info.iter().flatten().filter(|sheet| sheet.include).count();
+        self.merge_focus = (self.merge_focus + 1) % included.max(1);
+      }
+      Char(' ') => {
+        let sheet_count = self.merge_info.as_ref().map_or(0, |info| info.len());
+        if self.merge_selected < sheet_count {
+          if let Some(sheet) =
+            self.merge_info.as_mut().and_then(|info| info.get_mut(self.merge_selected))
+          {
+            sheet.include ^= true;
+          }
+          let included = self.merge_info.iter().flatten().filter(|sheet| sheet.include).count();
+          self.merge_focus = self.merge_focus.min(included.saturating_sub(1));
+        } else if let Some(column) = self.merge_rows().get(self.merge_selected - sheet_count) {
+          if !self.merge_dropped.remove(column) {
+            self.merge_dropped.insert(column.clone());
+          }
+        }
+      }
+      Left | Right => {
+        let sheet_count = self.merge_info.as_ref().map_or(0, |info| info.len());
+        if self.merge_selected < sheet_count {
+          self.adjust_merge_header(key.code == Right, source);
+        } else {
+          self.cycle_merge_pair(self.merge_selected - sheet_count, key.code == Right);
+        }
       }
       _ => {}
     }
   }
+  fn merge_distance(&self) -> usize {
+    if self.merge_fuzzy {
+      MERGE_EDIT_DISTANCE
+    } else {
+      0
+    }
+  }
+  fn cycle_merge_pair(&mut self, column_idx: usize, forward: bool) {
+    let Some(column) = self.merge_rows().get(column_idx).cloned() else { return };
+    if !self.primary_columns().contains(&column) {
+      return;
+    }
+    let focus = self.merge_focus;
+    let Some(sheet) = self.merge_info.iter_mut().flatten().filter(|sheet| sheet.include).nth(focus)
+    else {
+      return;
+    };
+    // Cycles through "not merged" and each of the sheet's header cells
+    let options: Vec<Option<&String>> =
+      std::iter::once(None).chain(sheet.header.iter().map(Some)).collect();
+    let current = options.iter().position(|&option| option == sheet.pairs.get(&column));
+    let next = match (current, forward) {
+      (Some(i), true) => (i + 1) % options.len(),
+      (Some(i), false) => (i + options.len() - 1) % options.len(),
+      (None, _) => 0,
+    };
+    match options[next].cloned() {
+      Some(cell) => sheet.pairs.insert(column, cell),
+      None => sheet.pairs.remove(&column),
+    };
+  }
+  fn adjust_merge_header(&mut self, forward: bool, source: &mut Source) {
+    let distance = self.merge_distance();
+    let Some(sheet) = self.merge_info.as_ref().and_then(|info| info.get(self.merge_selected))
+    else {
+      return;
+    };
+    let Some(rows) = source.rows(&sheet.name) else { return };
+    let header_row = if forward {
+      (sheet.header_row + 1).min(rows.len().saturating_sub(1))
+    } else {
+      sheet.header_row.saturating_sub(1)
+    };
+    let header = header_cells(&self.sheet_header(&rows, header_row));
+    let Some(primary_header) = self.data.get(self.first_row) else { return };
+    let pairs = pair_columns(primary_header, &header, distance);
+    if let Some(sheet) = self.merge_info.as_mut().and_then(|info| info.get_mut(self.merge_selected))
+    {
+      sheet.header_row = header_row;
+      sheet.header = header;
+      sheet.pairs = pairs;
+    }
+  }
+
   fn handle_col_select(&mut self, key: KeyEvent) {
     match key.code {
       Char(' ') => self.toggle_col_select(),
       Char('a') => self.toggle_all_col(),
+      Char('i') => self.invert_col_select(),
+      Char('e') => {
+        for (col, &empty) in self.columns.iter_mut().zip(&self.empty_columns) {
+          if empty {
+            *col = ColumnState::Hidden;
+          }
+        }
+      }
       Up | Down => {
         self.selected_column = navigate_index(self.selected_column, self.columns.len(), key.code);
       }
+      Home => self.selected_column = 0,
+      End => self.selected_column = self.columns.len().saturating_sub(1),
+      PageUp => self.selected_column = self.selected_column.saturating_sub(COLUMN_PAGE),
+      PageDown => {
+        self.selected_column =
+          (self.selected_column + COLUMN_PAGE).min(self.columns.len().saturating_sub(1));
+      }
       Enter => {
         self.selected_column = 0;
+        self.column_offset = 0;
         self.step = Step::Preview;
       }
       _ => {}
     }
   }
   fn handle_preview(&mut self, key: KeyEvent) {
+    if self.editing_filter {
+      match key.code {
+        Enter => self.editing_filter = false,
+        Esc => {
+          self.editing_filter = false;
+          self.row_filter = Input::default();
+          self.row_filter_column = None;
+        }
+        _ => {
+          self.row_filter.handle_event(&Key(key));
+        }
+      }
+      self.current_page = 0;
+      self.selected_row = 0;
+      return;
+    }
+    if self.editing_column_filter {
+      let Some(&col_idx) = self.visible_columns().get(self.selected_column) else { return };
+      match key.code {
+        Enter => match ColumnFilter::parse(self.column_filters[col_idx].value()) {
+          Ok(_) => self.editing_column_filter = false,
+          Err(err) => self.toast(format!("Invalid filter: {}", err)),
+        },
+        Esc => {
+          self.editing_column_filter = false;
+          self.column_filters[col_idx] = Input::default();
+        }
+        _ => {
+          self.column_filters[col_idx].handle_event(&Key(key));
+        }
+      }
+      self.current_page = 0;
+      self.selected_row = 0;
+      return;
+    }
+    if self.editing_search {
+      match key.code {
+        Enter => {
+          self.editing_search = false;
+          self.jump_to_match(true);
+        }
+        Esc => {
+          self.editing_search = false;
+          self.data_search = Input::default();
+        }
+        _ => {
+          self.data_search.handle_event(&Key(key));
+        }
+      }
+      return;
+    }
     match key.code {
+      Char('/') => {
+        self.data_search = Input::default();
+        self.editing_search = true;
+      }
+      Char('n') => self.jump_to_match(true),
+      Char('N') => self.jump_to_match(false),
+      Char('F') => {
+        self.editing_column_filter = self.visible_columns().get(self.selected_column).is_some();
+      }
+      Char('f') => {
+        self.row_filter_column = self.visible_columns().get(self.selected_column).copied();
+        self.editing_filter = self.row_filter_column.is_some();
+      }
+      Left if key.modifiers.contains(KeyModifiers::SHIFT) => {
+        self.column_offset = self.column_offset.saturating_sub(1);
+      }
+      Right if key.modifiers.contains(KeyModifiers::SHIFT) => {
+        let last = self.visible_columns().len().saturating_sub(1);
+        self.column_offset = (self.column_offset + 1).min(last);
+        self.selected_column = self.selected_column.max(self.column_offset);
+      }
       Left => self.prev_page(),
       Right => self.next_page(),
       Char(' ') => self.toggle_col_filter(),
       Char('+') => {
         self.rows_per_page = self.rows_per_page.saturating_add(1);
         self.current_page = 0;
+        self.selected_row = 0;
       }
       Char('-') => {
-        self.rows_per_page = self.rows_per_page.saturating_sub(1).max(5);
+        self.rows_per_page = self.rows_per_page.saturating_sub(1).max(MIN_ROWS_PER_PAGE);
         self.current_page = 0;
+        self.selected_row = 0;
       }
       Home => {
         self.current_page = 0;
+        self.selected_row = 0;
       }
       End => {
         self.current_page = self.total_pages().saturating_sub(1);
+        self.selected_row = 0;
+      }
+      Char('j') => self.move_row_cursor(true),
+      Char('k') => self.move_row_cursor(false),
+      Char('i') => self.show_column_stats(),
+      Char('x') => {
+        if let Some(&row_idx) = self.page_rows().get(self.selected_row) {
+          if !self.marked_rows.remove(&row_idx) {
+            self.marked_rows.insert(row_idx);
+          }
+          self.move_row_cursor(true);
+        }
+      }
+      Char('X') => self.keep_marked ^= true,
+      Char('w') => self.table_layout = self.table_layout.next(),
+      Char('t') => self.infer_types ^= true,
+      Char(c @ ('s' | 'S')) => {
+        if let Some(&col_idx) = self.visible_columns().get(self.selected_column) {
+          let sort = Some((col_idx, c == 'S'));
+          self.sort = if self.sort == sort { None } else { sort };
+          self.current_page = 0;
+          self.selected_row = 0;
+        }
+      }
+      Char('o') => {
+        self.any_non_empty ^= true;
+        self.current_page = 0;
+        self.selected_row = 0;
       }
       Up | Down => {
         let visible = self.visible_columns();
@@ -190,16 +707,48 @@ impl App {
     }
   }
   fn get_export_target(&mut self) -> Option<&mut Input> {
+    if self.export_focus_row == 0 {
+      return match self.export_edit {
+        ExportEdit::FileName => Some(&mut self.export_filename),
+        ExportEdit::Template => Some(&mut self.filename_template),
+        ExportEdit::OutDir => Some(&mut self.out_dir),
+        ExportEdit::NullTokens => Some(&mut self.null_tokens),
+        ExportEdit::TableName => Some(&mut self.table_name),
+        ExportEdit::XmlRoot => Some(&mut self.xml_root),
+        ExportEdit::XmlRecord => Some(&mut self.xml_record),
+        ExportEdit::RowsFrom => Some(&mut self.rows_from),
+        ExportEdit::RowsTo => Some(&mut self.rows_to),
+        ExportEdit::ItemsKey => Some(&mut self.items_key),
+        _ => None,
+      };
+    }
     let visible = self.visible_columns();
-    let col_idx = visible.get(self.export_focus_row.checked_sub(1)?)?;
+    let col_idx = visible.get(self.export_focus_row - 1)?;
     match self.export_edit {
       ExportEdit::KeyStr => Some(&mut self.custom_keys[*col_idx]),
       ExportEdit::Prefix => Some(&mut self.column_configs[*col_idx].prefix),
       ExportEdit::Postfix => Some(&mut self.column_configs[*col_idx].postfix),
-      ExportEdit::FileName | ExportEdit::Deduplicate => None,
+      ExportEdit::Min => Some(&mut self.column_configs[*col_idx].min),
+      ExportEdit::Max => Some(&mut self.column_configs[*col_idx].max),
+      ExportEdit::Pattern => Some(&mut self.column_configs[*col_idx].pattern),
+      ExportEdit::Truthy => Some(&mut self.column_configs[*col_idx].truthy),
+      ExportEdit::Falsy => Some(&mut self.column_configs[*col_idx].falsy),
+      ExportEdit::AffixTest => Some(&mut self.column_configs[*col_idx].affix_test),
+      ExportEdit::TrimChars => Some(&mut self.column_configs[*col_idx].trim_chars),
+      ExportEdit::Nulls => Some(&mut self.column_configs[*col_idx].nulls),
+      ExportEdit::DateFormat => Some(&mut self.column_configs[*col_idx].date_format),
+      _ => None,
+    }
+  }
+  fn next_column_choice(&self, current: Option<usize>) -> Option<usize> {
+    let visible = self.visible_columns();
+    match current {
+      None => visible.first().copied(),
+      Some(col) => visible.iter().skip_while(|&&c| c != col).nth(1).copied(),
     }
   }
   fn handle_export(&mut self, key: KeyEvent) {
+    let space = key.code == Char(' ');
     match key.code {
       Up | Down => {
         let total_items = self.visible_columns().len() + 2;
@@ -209,48 +758,200 @@ impl App {
           if self.export_focus_row == 0 { ExportEdit::FileName } else { ExportEdit::KeyStr };
       }
       Tab => {
-        self.export_edit = if self.export_focus_row == 0 {
-          match self.export_edit {
-            ExportEdit::FileName => ExportEdit::Deduplicate,
-            _ => ExportEdit::FileName,
+        let fields: Vec<ExportEdit> =
+          if self.export_focus_row == 0 { ExportEdit::GLOBAL } else { ExportEdit::COLUMN }
+            .iter()
+            .copied()
+            .filter(|&f| {
+              self.field_applies(f)
+                && self.focused_config().is_none_or(|config| config.applies(f, self.typed_values))
+            })
+            .collect();
+        let pos = fields.iter().position(|&f| f == self.export_edit).unwrap_or(0);
+        self.export_edit = fields[(pos + 1) % fields.len()];
+      }
+      Enter => self.show_export_summary(),
+      _ => match (self.export_focus_row, self.export_edit) {
+        (0, ExportEdit::Deduplicate) if space => self.deduplicate ^= true,
+        (0, ExportEdit::Append) if space => self.append ^= true,
+        (0, ExportEdit::Schema) if space => self.write_schema ^= true,
+        (0, ExportEdit::Manifest) if space => self.write_manifest ^= true,
+        (0, ExportEdit::Matrix) if space => self.matrix ^= true,
+        (0, ExportEdit::VerbatimKeys) if space => self.verbatim_keys ^= true,
+        (0, ExportEdit::TrimValues) if space => self.trim_values ^= true,
+        (0, ExportEdit::TypedValues) if space => self.typed_values ^= true,
+        (0, ExportEdit::Bom) if space => self.bom ^= true,
+        (0, ExportEdit::Crlf) if space => self.crlf ^= true,
+        (0, ExportEdit::Indent) if space => self.indent = self.indent.next(),
+        (0, ExportEdit::SqlDialect) if space => self.sql_dialect = self.sql_dialect.next(),
+        (0, ExportEdit::Compact) if space => self.compact ^= true,
+        (0, ExportEdit::NormalizeDedup) if space => self.normalize_dedup ^= true,
+        (0, ExportEdit::Format) if space => self.export_format = self.export_format.next(),
+        (0, ExportEdit::Partition) if space => {
+          self.partition_column = self.next_column_choice(self.partition_column);
+        }
+        (0, ExportEdit::GroupBy) if space => {
+          self.group_column = self.next_column_choice(self.group_column);
+        }
+        (0, ExportEdit::DropPartition) if space => self.drop_partition ^= true,
+        (row, ExportEdit::Required) if space => {
+          if let Some(&col_idx) = self.visible_columns().get(row - 1) {
+            self.column_configs[col_idx].required ^= true;
           }
-        } else {
-          match self.export_edit {
-            ExportEdit::KeyStr => ExportEdit::Prefix,
-            ExportEdit::Prefix => ExportEdit::Postfix,
-            _ => ExportEdit::KeyStr,
+        }
+        (row, ExportEdit::DedupKey) if space => {
+          if let Some(&col_idx) = self.visible_columns().get(row - 1) {
+            self.column_configs[col_idx].dedup_key ^= true;
           }
-        };
+        }
+        (row, ExportEdit::Exclude) if space => {
+          if let Some(&col_idx) = self.visible_columns().get(row - 1) {
+            self.column_configs[col_idx].exclude ^= true;
+          }
+        }
+        (row, ExportEdit::TrimAll) if space => {
+          if let Some(&col_idx) = self.visible_columns().get(row - 1) {
+            self.column_configs[col_idx].trim_all ^= true;
+          }
+        }
+        (row, ExportEdit::Trim) if space => {
+          if let Some(&col_idx) = self.visible_columns().get(row - 1) {
+            let config = &mut self.column_configs[col_idx];
+            config.trim = match config.trim {
+              None => Some(true),
+              Some(true) => Some(false),
+              Some(false) => None,
+            };
+          }
+        }
+        (row, ExportEdit::ValueType) if space => {
+          if let Some(&col_idx) = self.visible_columns().get(row - 1) {
+            let config = &mut self.column_configs[col_idx];
+            config.value_type = ColumnType::next_override(config.value_type);
+          }
+        }
+        (row, ExportEdit::AffixWhen) if space => {
+          if let Some(&col_idx) = self.visible_columns().get(row - 1) {
+            let config = &mut self.column_configs[col_idx];
+            config.affix_when = config.affix_when.next();
+          }
+        }
+        (row, ExportEdit::AffixTyped) if space => {
+          if let Some(&col_idx) = self.visible_columns().get(row - 1) {
+            self.column_configs[col_idx].affix_typed ^= true;
+          }
+        }
+        (row, ExportEdit::BoolMap) if space => {
+          if let Some(&col_idx) = self.visible_columns().get(row - 1) {
+            self.column_configs[col_idx].bool_map ^= true;
+          }
+        }
+        (row, ExportEdit::NumberFormat) if space => {
+          if let Some(&col_idx) = self.visible_columns().get(row - 1) {
+            let config = &mut self.column_configs[col_idx];
+            config.number_format = config.number_format.next();
+          }
+        }
+        _ => {
+          if let Some(target) = self.get_export_target() {
+            target.handle_event(&Key(key));
+          }
+          self.filename_edited |=
+            self.export_focus_row == 0 && self.export_edit == ExportEdit::FileName;
+        }
+      },
+    }
+  }
+  fn jump_to_match(&mut self, forward: bool) {
+    let matches = self.search_matches();
+    let cursor = self.cursor_position();
+    let target = if forward {
+      matches.iter().find(|&&m| m > cursor).or(matches.first())
+    } else {
+      matches.iter().rev().find(|&&m| m < cursor).or(matches.last())
+    };
+    match target {
+      Some(&(pos, col)) => {
+        self.current_page = pos / self.page_size();
+        self.selected_row = pos % self.page_size();
+        self.selected_column = col;
       }
-      Enter => self.export_to_json(),
-      _ => {
-        match self.export_focus_row {
-          0 => match self.export_edit {
-            ExportEdit::FileName => {
-              self.export_filename.handle_event(&Key(key));
-            }
-            ExportEdit::Deduplicate if key.code == Char(' ') => {
-              self.deduplicate ^= true;
-            }
-            _ => {}
-          },
-          _ => {
-            if let Some(target) = self.get_export_target() {
-              target.handle_event(&Key(key));
-            }
+      None if !self.data_search.value().is_empty() => {
+        self.toast(format!("No matches for '{}'", self.data_search.value()));
+      }
+      None => {}
+    }
+  }
+  fn move_row_cursor(&mut self, forward: bool) {
+    let page_len = self.page_rows().len();
+    if forward && self.selected_row + 1 < page_len {
+      self.selected_row += 1;
+    } else if forward && self.current_page + 1 < self.total_pages() {
+      self.next_page();
+    } else if !forward && self.selected_row > 0 {
+      self.selected_row -= 1;
+    } else if !forward && self.current_page > 0 {
+      self.prev_page();
+      self.selected_row = self.page_rows().len().saturating_sub(1);
+    }
+  }
+  fn handle_mouse(&mut self, mouse: MouseEvent, width: u16) {
+    let row = mouse.row as usize;
+    match (self.step, mouse.kind) {
+      (_, MouseEventKind::Down(MouseButton::Left)) if self.popup.is_some() => self.popup = None,
+      (Step::SheetSelect, MouseEventKind::Down(MouseButton::Left)) => {
+        let entry = row.checked_sub(1).and_then(|i| {
+          if self.sheet_search.value().is_empty() {
+            (i < self.sheets.len()).then_some(i)
+          } else {
+            self.matching_sheets.get(i).copied()
           }
-        };
+        });
+        if entry.is_some() {
+          self.selected_sheet = entry;
+        }
+      }
+      (Step::ColSelect, MouseEventKind::Down(MouseButton::Left)) => {
+        let entry = row.checked_sub(1).map(|i| i + self.list_scroll);
+        if let Some(i) = entry.filter(|&i| i < self.columns.len()) {
+          self.selected_column = i;
+        }
       }
+      (Step::Preview, MouseEventKind::Down(MouseButton::Left))
+        if row < self.visible_columns().len() =>
+      {
+        self.selected_column = row;
+      }
+      (Step::Preview, MouseEventKind::ScrollDown) => self.move_row_cursor(true),
+      (Step::Preview, MouseEventKind::ScrollUp) => self.move_row_cursor(false),
+      (Step::Export, MouseEventKind::Down(MouseButton::Left)) => {
+        let column = mouse.column as usize;
+        let clicked =
+          self.export_header_layout(width).into_iter().find(|(line, start, label, value, _)| {
+            let value_start = start + visual_width(label);
+            *line == row && (value_start..value_start + visual_width(value)).contains(&column)
+          });
+        if let Some((.., edit)) = clicked {
+          self.export_focus_row = 0;
+          self.export_edit = edit;
+          if self.get_export_target().is_none() {
+            self.handle_export(KeyEvent::new(Char(' '), KeyModifiers::NONE));
+          }
+        }
+      }
+      _ => {}
     }
   }
   fn next_page(&mut self) {
     if self.current_page + 1 < self.total_pages() {
       self.current_page += 1;
+      self.selected_row = 0;
     }
   }
   fn prev_page(&mut self) {
     if self.current_page > 0 {
       self.current_page -= 1;
+      self.selected_row = 0;
     }
   }
   fn toggle_col_filter(&mut self) {
@@ -259,6 +960,8 @@ impl App {
         ColumnState::Original => ColumnState::NonEmpty,
         _ => ColumnState::Original,
       };
+      self.current_page = self.current_page.min(self.total_pages().saturating_sub(1));
+      self.selected_row = 0;
     }
   }
   fn toggle_col_select(&mut self) {
@@ -273,16 +976,46 @@ impl App {
     let all_hidden = self.columns.iter().all(|&c| matches!(c, ColumnState::Hidden));
     self.columns.fill(if all_hidden { ColumnState::NonEmpty } else { ColumnState::Hidden });
   }
+  fn update_empty_columns(&mut self) {
+    let data_rows = &self.data[(self.first_row + 1).min(self.data.len())..];
+    self.empty_columns = (0..self.columns.len())
+      .map(|col_idx| {
+        data_rows.iter().all(|row| row.get(col_idx).is_none_or(|cell| cell.trim().is_empty()))
+      })
+      .collect();
+  }
+  fn invert_col_select(&mut self) {
+    for col in self.columns.iter_mut() {
+      *col = match *col {
+        ColumnState::Hidden => ColumnState::NonEmpty,
+        _ => ColumnState::Hidden,
+      };
+    }
+  }
   fn update_sheet_search(&mut self) {
-    let search_lower = self.sheet_search.value().to_lowercase();
-    self.matching_sheets = self
+    let query = self.sheet_search.value();
+    let matcher = SkimMatcherV2::default();
+    let mut scored: Vec<(i64, usize)> = self
       .sheets
       .iter()
       .enumerate()
-      .filter_map(|(i, name)| (name.to_lowercase().contains(&search_lower)).then(|| i))
+      .filter_map(|(i, name)| matcher.fuzzy_match(name, query).map(|score| (score, i)))
       .collect();
+    if !query.is_empty() {
+      scored.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+    }
+    self.matching_sheets = scored.into_iter().map(|(_, i)| i).collect();
     self.selected_sheet = self.matching_sheets.first().copied().or(self.selected_sheet);
   }
+  fn sheet_item(&self, idx: usize) -> ListItem<'static> {
+    let name = &self.sheets[idx];
+    let style = if Some(idx) == self.selected_sheet { FOCUSED_STYLE } else { Style::default() };
+    let mut spans = vec![Span::styled(name.clone(), style)];
+    if let Some(hint) = self.range_hints.get(name) {
+      spans.push(Span::styled(format!("  ({})", hint), Style::default().fg(Color::DarkGray)));
+    }
+    ListItem::new(Line::from(spans))
+  }
   fn input_style(&self, is_selected: bool, edit_mode: ExportEdit) -> Style {
     if !is_selected {
       return Style::default();
@@ -293,134 +1026,1177 @@ impl App {
       FOCUSED_STYLE
     }
   }
-  fn export_to_json(&mut self) {
-    let filename = if !self.export_filename.value().is_empty() {
-      normalize_text(self.export_filename.value())
-    } else {
-      self.get_default_filename()
+  fn save_profile(&mut self) {
+    let Some(header) = self.data.get(self.first_row) else { return };
+    let profile = Profile {
+      sheet: self.selected_sheet.map_or(String::new(), |idx| self.sheets[idx].clone()),
+      first_row: match self.header_backup {
+        Some(_) => self.first_row + 1 - self.header_rows,
+        None => self.first_row,
+      },
+      header_rows: self.header_rows,
+      header_separator: self.header_separator.value().to_string(),
+      last_row: self.last_row_input.value().trim().to_string(),
+      stop_at_empty: self.stop_at_empty,
+      filter_column: self
+        .row_filter_column
+        .and_then(|col_idx| header.get(col_idx))
+        .map_or(String::new(), |name| name.trim().to_string()),
+      filter: self.row_filter.value().to_string(),
+      columns: header
+        .iter()
+        .enumerate()
+        .map(|(col_idx, name)| ColumnProfile {
+          name: name.trim().to_string(),
+          state: self.columns[col_idx],
+          key: self.custom_keys[col_idx].value().to_string(),
+          filter: self.column_filters[col_idx].value().to_string(),
+          config: self.column_configs[col_idx].clone(),
+        })
+        .collect(),
+    };
+    match profile.save(&self.original_filename) {
+      Ok(path) => self.toast(format!("Saved profile to {}", path.display())),
+      Err(err) => self.toast(format!("Could not save profile: {}", err)),
+    }
+  }
+  fn load_profile(&mut self, source: &mut Source) {
+    let profile = match Profile::load(&self.original_filename) {
+      Ok(profile) => profile,
+      Err(err) => {
+        self.toast(format!("Could not load profile: {}", err));
+        return;
+      }
+    };
+    let Some(idx) = self.sheets.iter().position(|name| *name == profile.sheet) else {
+      self.toast(format!("Profile sheet '{}' is not in this workbook", profile.sheet));
+      return;
+    };
+    self.selected_sheet = Some(idx);
+    if !self.load_sheet(source) {
+      return;
+    }
+    self.header_rows = profile.header_rows.max(1);
+    self.header_separator = Input::default().with_value(profile.header_separator);
+    if profile.first_row + self.header_rows > self.data.len() {
+      self.toast(format!("Profile header row {} is past the end of the sheet", profile.first_row));
+      return;
+    }
+    self.row_input = Input::default().with_value(profile.first_row.to_string());
+    self.last_row_input = Input::default().with_value(profile.last_row);
+    self.stop_at_empty = profile.stop_at_empty;
+    if let Err(err) = self.trim_footer(profile.first_row + self.header_rows - 1) {
+      self.toast(format!("Profile: {}", err));
+      return;
+    }
+    self.first_row = self.combine_header(profile.first_row);
+    self.update_empty_columns();
+    let header: Vec<String> =
+      self.data[self.first_row].iter().map(|name| name.trim().to_string()).collect();
+    let mut matched = 0;
+    for column in profile.columns {
+      if let Some(col_idx) = header.iter().position(|name| *name == column.name) {
+        self.columns[col_idx] = column.state;
+        self.custom_keys[col_idx] = Input::default().with_value(column.key);
+        self.column_filters[col_idx] = Input::default().with_value(column.filter);
+        self.column_configs[col_idx] = column.config;
+        matched += 1;
+      }
+    }
+    self.row_filter_column = header.iter().position(|name| *name == profile.filter_column);
+    self.row_filter = Input::default().with_value(profile.filter);
+    self.current_page = 0;
+    self.selected_row = 0;
+    self.selected_column = 0;
+    self.column_offset = 0;
+    self.popup = None;
+    self.step = Step::ColSelect;
+    self.toast(format!("Applied profile to {} of {} columns", matched, header.len()));
+  }
+  fn reset_export(&mut self) {
+    let before = self.snapshot();
+    let config = if self.use_config { Config::load() } else { Config::default() };
+    let col_count = self.columns.len();
+    self.column_configs = vec![ColumnConfig::default(); col_count];
+    self.custom_keys = vec![Input::default(); col_count];
+    self.deduplicate = config.deduplicate;
+    self.export_format = config.format;
+    self.export_filename = Input::default().with_value(self.get_default_filename());
+    self.export_focus_row = 0;
+    self.export_edit = ExportEdit::FileName;
+    self.record_history(before);
+    self.toast("Export settings reset".to_string());
+  }
+  fn toast(&mut self, message: String) {
+    self.export_toast = Some(message);
+    self.export_toast_time = Some(Instant::now());
+  }
+  fn export_header_fields(&self) -> Vec<(&'static str, String, ExportEdit)> {
+    let toggle = |on: bool| if on { " ● " } else { " ◯ " }.to_string();
+    let column_name = |choice: Option<usize>| {
+      choice
+        .and_then(|col_idx| self.data[self.first_row].get(col_idx))
+        .map_or("none".to_string(), |s| display_cell(s))
+    };
+    vec![
+      ("Filename: ", self.export_filename.to_string(), ExportEdit::FileName),
+      ("   Template: ", self.filename_template.to_string(), ExportEdit::Template),
+      ("   Directory: ", self.out_dir.to_string(), ExportEdit::OutDir),
+      ("   Verbatim keys ", toggle(self.verbatim_keys), ExportEdit::VerbatimKeys),
+      ("   Trim values ", toggle(self.trim_values), ExportEdit::TrimValues),
+      (" nulls: ", self.null_tokens.to_string(), ExportEdit::NullTokens),
+      ("   Typed ", toggle(self.typed_values), ExportEdit::TypedValues),
+      ("   Format ", format!(" {} ", self.export_format.extension()), ExportEdit::Format),
+      ("   Table: ", self.table_name.to_string(), ExportEdit::TableName),
+      (" dialect ", format!(" {} ", self.sql_dialect.label()), ExportEdit::SqlDialect),
+      ("   Root: ", self.xml_root.to_string(), ExportEdit::XmlRoot),
+      (" record: ", self.xml_record.to_string(), ExportEdit::XmlRecord),
+      ("   Deduplicate ", toggle(self.deduplicate), ExportEdit::Deduplicate),
+      (" normalized ", toggle(self.normalize_dedup), ExportEdit::NormalizeDedup),
+      ("   Append ", toggle(self.append), ExportEdit::Append),
+      ("   Schema ", toggle(self.write_schema), ExportEdit::Schema),
+      ("   Manifest ", toggle(self.write_manifest), ExportEdit::Manifest),
+      ("   Matrix ", toggle(self.matrix), ExportEdit::Matrix),
+      ("   BOM ", toggle(self.bom), ExportEdit::Bom),
+      ("   CRLF ", toggle(self.crlf), ExportEdit::Crlf),
+      ("   Indent ", format!(" {} ", self.indent.label()), ExportEdit::Indent),
+      (" compact ", toggle(self.compact), ExportEdit::Compact),
+      ("   Partition ", format!(" {} ", column_name(self.partition_column)), ExportEdit::Partition),
+      ("   Drop ", toggle(self.drop_partition), ExportEdit::DropPartition),
+      ("   Rows from ", format!("{:<4}", self.rows_from.value()), ExportEdit::RowsFrom),
+      (" to ", format!("{:<4}", self.rows_to.value()), ExportEdit::RowsTo),
+      ("   Group by ", format!(" {} ", column_name(self.group_column)), ExportEdit::GroupBy),
+      (" items key: ", self.items_key.to_string(), ExportEdit::ItemsKey),
+    ]
+    .into_iter()
+    .filter(|(_, _, edit)| self.field_applies(*edit))
+    .collect()
+  }
+  fn focused_config(&self) -> Option<&ColumnConfig> {
+    let col_idx = *self.visible_columns().get(self.export_focus_row.checked_sub(1)?)?;
+    self.column_configs.get(col_idx)
+  }
+  fn field_applies(&self, edit: ExportEdit) -> bool {
+    match edit {
+      ExportEdit::TableName => self.export_format.has_table(),
+      ExportEdit::SqlDialect => self.export_format == ExportFormat::Sql,
+      ExportEdit::XmlRoot | ExportEdit::XmlRecord => self.export_format == ExportFormat::Xml,
+      ExportEdit::DedupKey | ExportEdit::NormalizeDedup => self.deduplicate,
+      ExportEdit::Matrix => {
+        matches!(self.export_format, ExportFormat::Json | ExportFormat::Yaml | ExportFormat::Toml)
+      }
+      ExportEdit::GroupBy | ExportEdit::ItemsKey => !self.matrix_output(),
+      ExportEdit::Bom | ExportEdit::Crlf => self.export_format.is_text(),
+      ExportEdit::Indent => self.export_format == ExportFormat::Json && !self.compact,
+      ExportEdit::Compact => self.export_format == ExportFormat::Json,
+      _ => true,
+    }
+  }
+  // Places each header field on a line, moving it to the next one when it would cross the right
+  // edge, so drawing and mouse clicks agree on where every field is
+  fn export_header_layout(
+    &self,
+    width: u16,
+  ) -> Vec<(usize, usize, &'static str, String, ExportEdit)> {
+    let width = width.max(1) as usize;
+    let (mut line, mut column) = (0, 0);
+    self
+      .export_header_fields()
+      .into_iter()
+      .map(|(label, value, edit)| {
+        let field_width = visual_width(label) + visual_width(&value);
+        if column > 0 && column + field_width > width {
+          line += 1;
+          column = 0;
+        }
+        column += field_width;
+        (line, column - field_width, label, value, edit)
+      })
+      .collect()
+  }
+  fn export_header(&self, width: u16) -> Vec<Line<'static>> {
+    let mut lines: Vec<Vec<Span>> = Vec::new();
+    for (line, _, label, value, edit) in self.export_header_layout(width) {
+      if lines.len() <= line {
+        lines.push(Vec::new());
+      }
+      let style = self.input_style(self.export_focus_row == 0 && self.export_edit == edit, edit);
+      lines[line].extend([Span::raw(label), Span::styled(value, style)]);
+    }
+    lines.into_iter().map(Line::from).collect()
+  }
+  fn column_extras(&self, col_idx: usize) -> Vec<(&'static str, String, bool, ExportEdit)> {
+    let config = &self.column_configs[col_idx];
+    let input = |label, input: &Input, edit| {
+      (label, format!("{:<8}", input.value()), !input.value().is_empty(), edit)
+    };
+    let toggle =
+      |label, on: bool, edit| (label, (if on { " ● " } else { " ◯ " }).to_string(), on, edit);
+    vec![
+      toggle(" exclude ", config.exclude, ExportEdit::Exclude),
+      input(" strip: ", &config.trim_chars, ExportEdit::TrimChars),
+      (
+        " from ",
+        (if config.trim_all { " anywhere " } else { " ends " }).to_string(),
+        config.trim_all,
+        ExportEdit::TrimAll,
+      ),
+      (
+        " trim ",
+        format!(
+          " {} ",
+          match config.trim {
+            None => "global",
+            Some(true) => "on",
+            Some(false) => "off",
+          }
+        ),
+        config.trim.is_some(),
+        ExportEdit::Trim,
+      ),
+      input(" nulls: ", &config.nulls, ExportEdit::Nulls),
+      (
+        " type ",
+        format!(" {} ", config.value_type.map_or("auto", |value_type| value_type.label())),
+        config.value_type.is_some(),
+        ExportEdit::ValueType,
+      ),
+      input(" date: ", &config.date_format, ExportEdit::DateFormat),
+      (
+        " affix ",
+        format!(" {} ", config.affix_when.label()),
+        config.affix_when != AffixWhen::Always,
+        ExportEdit::AffixWhen,
+      ),
+      input(" test: ", &config.affix_test, ExportEdit::AffixTest),
+      toggle(" required ", config.required, ExportEdit::Required),
+      toggle(" dedup key ", config.dedup_key, ExportEdit::DedupKey),
+      (
+        " number ",
+        format!(" {} ", config.number_format.label()),
+        config.number_format != NumberFormat::AsIs,
+        ExportEdit::NumberFormat,
+      ),
+      input(" min: ", &config.min, ExportEdit::Min),
+      input(" max: ", &config.max, ExportEdit::Max),
+      input(" regex: ", &config.pattern, ExportEdit::Pattern),
+      (
+        " bool ",
+        (if config.bool_map { " ● " } else { " ◯ " }).to_string(),
+        false,
+        ExportEdit::BoolMap,
+      ),
+      input(" true: ", &config.truthy, ExportEdit::Truthy),
+      input(" false: ", &config.falsy, ExportEdit::Falsy),
+      toggle(" affix typed ", config.affix_typed, ExportEdit::AffixTyped),
+    ]
+    .into_iter()
+    .filter(|&(_, _, _, edit)| self.field_applies(edit) && config.applies(edit, self.typed_values))
+    .collect()
+  }
+  fn export_sheet(&mut self, progress: &mut dyn FnMut(usize, usize) -> bool) {
+    match self.export_with_progress(true, progress) {
+      Ok(message) => {
+        self.toast(message);
+        self.save_config();
+      }
+      Err(message) => self.toast(message),
+    }
+  }
+  fn export(&self, validate: bool) -> Result<String, String> {
+    self.export_with_progress(validate, &mut |_, _| true)
+  }
+  fn export_with_progress(
+    &self,
+    validate: bool,
+    progress: &mut dyn FnMut(usize, usize) -> bool,
+  ) -> Result<String, String> {
+    match self.validation_errors() {
+      Ok(errors) if errors.is_empty() || !validate => {}
+      Ok(errors) => return Err(format!("Export aborted: {}", errors.join(", "))),
+      Err(message) => return Err(format!("Export aborted: {}", message)),
+    }
+    let name = self.export_name();
+    let filename = self.output_path(&name).map_err(|err| format!("Export failed: {}", err))?;
+    let extension = self.file_extension();
+    let rows = self.export_rows();
+    let total = rows.len();
+    let batches = match self.partition_column {
+      Some(col_idx) => group_ordered(rows, |&row_idx| {
+        safe_filename(self.data[row_idx].get(col_idx).map_or("", |s| s.trim()))
+      })
+      .into_iter()
+      .map(|(value, rows)| (format!("{}_{}.{}", filename, value, extension), rows))
+      .collect(),
+      None => vec![(format!("{}.{}", filename, extension), rows)],
+    };
+    let mut files = Vec::with_capacity(batches.len());
+    let mut done = 0;
+    for (filepath, rows) in batches {
+      let records = self
+        .records_with_progress(&rows, &mut |n| progress(done + n, total))
+        .ok_or("Export cancelled")?;
+      done += rows.len();
+      files.push((filepath, records));
+    }
+    // The schema, manifest and duplicate count come from the records built above, so they
+    // follow the same cancel checks instead of rebuilding every record afterwards
+    let records: Vec<serde_json::Value> =
+      files.iter().flat_map(|(_, records)| records.iter().cloned()).collect();
+    let schema = self.write_schema.then(|| {
+      // A dotted key that could not be nested stays a flat property, so both forms are listed
+      let paths = self.key_paths();
+      let required: HashSet<Vec<String>> = self
+        .export_columns()
+        .into_iter()
+        .filter(|&col_idx| self.column_configs[col_idx].required)
+        .map(|col_idx| self.field_name(col_idx))
+        .flat_map(|key| {
+          let nested = paths.iter().find(|(field, _)| *field == key).map(|(_, path)| path.clone());
+          std::iter::once(vec![key]).chain(nested)
+        })
+        .collect();
+      export::json_schema(&self.shape_records(records.clone()), &required, &name)
+    });
+    let records = self.dedup_records(records);
+    let duplicates = self.duplicate_ids(&records);
+    let manifest = self.write_manifest.then(|| self.manifest(records.len()));
+    let result = match self.partition_column {
+      Some(_) => {
+        let count = files.len();
+        files
+          .into_iter()
+          .try_for_each(|(filepath, records)| self.write_records(&filepath, records).map(|_| ()))
+          .map(|_| format!("Exported {} files to {}_*.{}", count, filename, extension))
+      }
+      None => files
+        .pop()
+        .map_or(Ok(String::new()), |(filepath, records)| self.write_records(&filepath, records)),
+    };
+    let result = result.and_then(|message| {
+      let Some(schema) = schema else { return Ok(message) };
+      let text = format!("{}\n", export::to_json(&schema, self.json_indent())?);
+      write_atomic(&format!("{}.schema.json", filename), text.as_bytes())?;
+      Ok(format!("{} (with schema)", message))
+    });
+    let result = result.and_then(|message| {
+      let Some(manifest) = manifest else { return Ok(message) };
+      let text = format!("{}\n", export::to_json(&manifest, self.json_indent())?);
+      write_atomic(&format!("{}.meta.json", filename), text.as_bytes())?;
+      Ok(format!("{} (with manifest)", message))
+    });
+    result
+      .map(|message| match duplicates {
+        0 => message,
+        count => format!("{} · {} duplicate IDs detected", message, count),
+      })
+      .map_err(|err| format!("Export failed: {}", err))
+  }
+  fn copy_export(&mut self) {
+    let format = self.export_format;
+    if !format.is_text() {
+      self.toast(format!("Can't copy {} to the clipboard, pick a text format", format.extension()));
+      return;
+    }
+    match self.validation_errors() {
+      Ok(errors) if errors.is_empty() => {}
+      Ok(errors) => return self.toast(format!("Copy aborted: {}", errors.join(", "))),
+      Err(message) => return self.toast(format!("Copy aborted: {}", message)),
+    }
+    let records = self.shape_records(self.dedup_records(self.create_json_records()));
+    let options = self.export_options(&self.export_name());
+    let text = export::serialize(format, &records, &options)
+      .map_err(|err| err.to_string())
+      .and_then(|bytes| String::from_utf8(bytes).map_err(|err| err.to_string()));
+    // The clipboard is kept for the whole session: on X11 the copied text
+    // is only available while its owner is alive
+    let copied = text.and_then(|text| {
+      if self.clipboard.is_none() {
+        self.clipboard = Some(arboard::Clipboard::new().map_err(|err| err.to_string())?);
+      }
+      let clipboard = self.clipboard.as_mut().ok_or("No clipboard")?;
+      clipboard.set_text(text).map_err(|err| err.to_string())
+    });
+    self.toast(match copied {
+      Ok(()) => {
+        format!("Copied {} records as {} to the clipboard", records.len(), format.extension())
+      }
+      Err(err) => format!("Copy failed: {}", err),
+    });
+  }
+  // Counts repeated IDs among records that have already been deduplicated
+  fn duplicate_ids(&self, records: &[serde_json::Value]) -> usize {
+    let Some(&id_col) = self.export_columns().first().filter(|_| self.deduplicate) else {
+      return 0;
+    };
+    let key = self.field_name(id_col);
+    let distinct: HashSet<String> = records.iter().map(|record| record[&key].to_string()).collect();
+    records.len() - distinct.len()
+  }
+  fn configure_headless(&mut self, cli: &Cli) -> Result<(), String> {
+    let first_row = cli.first_row.unwrap_or(0);
+    self.header_rows = cli.header_rows.unwrap_or(1);
+    if first_row + self.header_rows > self.data.len() {
+      return Err(format!("First row {} is past the end of the sheet", first_row));
+    }
+    if let Some(last_row) = cli.last_row {
+      self.last_row_input = Input::default().with_value(last_row.to_string());
+      self.trim_footer(first_row + self.header_rows - 1)?;
+    }
+    self.first_row = self.combine_header(first_row);
+    self.update_empty_columns();
+    if cli.columns.is_empty() {
+      self.columns.fill(ColumnState::Original);
+    } else {
+      self.columns.fill(ColumnState::Hidden);
+      for name in &cli.columns {
+        let idx = self.data[self.first_row]
+          .iter()
+          .position(|cell| normalize_text(cell.trim()) == normalize_text(name.trim()))
+          .ok_or(format!("No column named '{}'", name))?;
+        self.columns[idx] = ColumnState::Original;
+      }
+    }
+    if let Some(format) = cli.format {
+      self.export_format = format;
+    }
+    if let Some(dir) = &cli.out_dir {
+      self.out_dir = Input::default().with_value(dir.clone());
+    }
+    self.bom |= cli.bom;
+    self.crlf |= cli.crlf;
+    self.compact |= cli.compact;
+    self.typed_values |= cli.typed;
+    self.write_schema |= cli.schema;
+    if let Some(indent) = cli.indent {
+      self.indent = indent;
+    }
+    if let Some(dialect) = cli.dialect {
+      self.sql_dialect = dialect;
+    }
+    let offset = cli.offset.unwrap_or(0);
+    if offset > 0 {
+      self.rows_from = Input::default().with_value((offset + 1).to_string());
+    }
+    if let Some(limit) = cli.limit {
+      self.rows_to = Input::default().with_value((offset + limit).to_string());
+    }
+    self.export_filename = Input::default().with_value(self.file_stem());
+    if let Some(out) = &cli.out {
+      let path = Path::new(out);
+      let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+      if cli.format.is_none() {
+        self.export_format = ExportFormat::from_extension(extension)
+          .ok_or(format!("Can't tell the format of '{}', pass --format", out))?;
+      }
+      if ExportFormat::from_extension(extension) != Some(self.export_format) {
+        return Err(format!("--out '{}' should end in .{}", out, self.export_format.extension()));
+      }
+      let dir = path.parent().map_or(String::new(), |dir| dir.to_string_lossy().to_string());
+      let stem = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().to_string());
+      self.out_dir = Input::default().with_value(dir);
+      self.filename_template = Input::default();
+      self.export_filename = Input::default().with_value(stem);
+      self.out_extension = Some(extension.to_string());
+    }
+    Ok(())
+  }
+  fn file_extension(&self) -> &str {
+    self.out_extension.as_deref().unwrap_or(self.export_format.extension())
+  }
+  fn export_name(&self) -> String {
+    // An explicit --out path is used as given
+    if self.out_extension.is_some() {
+      self.export_filename.value().to_string()
+    } else if self.filename_edited && !self.export_filename.value().is_empty() {
+      self.resolve_template(self.export_filename.value())
+    } else if !self.filename_template.value().trim().is_empty() {
+      self.resolve_template(self.filename_template.value())
+    } else if !self.export_filename.value().is_empty() {
+      self.resolve_template(self.export_filename.value())
+    } else {
+      self.get_default_filename()
+    }
+  }
+  fn show_export_summary(&mut self) {
+    let filename = match self.output_path(&self.export_name()) {
+      Ok(filename) => filename,
+      Err(err) => {
+        self.toast(format!("Export failed: {}", err));
+        return;
+      }
+    };
+    let extension = self.file_extension();
+    let target = match self.partition_column {
+      Some(_) => format!("{}_*.{}", filename, extension),
+      None => format!("{}.{}", filename, extension),
+    };
+    // Counting rows instead of building the records keeps the popup instant on big sheets
+    let rows = self.export_rows().len();
+    let mut filters: Vec<String> = self
+      .active_column_filters()
+      .into_iter()
+      .map(|(col_idx, _)| {
+        let name = display_cell(&self.data[self.first_row][col_idx]);
+        format!("{} {}", name, self.column_filters[col_idx].value().trim())
+      })
+      .collect();
+    if let Some(col_idx) = self.row_filter_column.filter(|_| !self.row_filter.value().is_empty()) {
+      filters.push(format!(
+        "{} contains \"{}\"",
+        display_cell(&self.data[self.first_row][col_idx]),
+        self.row_filter.value()
+      ));
+    }
+    let filter = if filters.is_empty() { "none".to_string() } else { filters.join(", ") };
+    let sort = match self.sort {
+      Some((col_idx, descending)) => format!(
+        "{} {}",
+        display_cell(&self.data[self.first_row][col_idx]),
+        if descending { "descending" } else { "ascending" }
+      ),
+      None => "none".to_string(),
+    };
+    let on_off = |flag: bool| if flag { "on" } else { "off" };
+    self.popup_text = [
+      format!("Target:  {}", target),
+      format!("Format:  {}", extension),
+      format!("Rows:    {}", rows),
+      format!("Columns: {}", self.export_columns().len()),
+      format!("Dedup:   {}", on_off(self.deduplicate)),
+      format!("Append:  {}", on_off(self.append)),
+      format!("Filter:  {}", filter),
+      format!("Sort:    {}", sort),
+    ]
+    .join("\n");
+    self.popup_changed.clear();
+    self.popup_scroll = 0;
+    self.popup = Some(Popup::Confirm);
+  }
+  fn output_path(&self, name: &str) -> std::io::Result<String> {
+    let dir = self.out_dir.value().trim();
+    if dir.is_empty() {
+      return Ok(name.to_string());
+    }
+    fs::create_dir_all(dir)?;
+    Ok(fs::canonicalize(dir)?.join(name).to_string_lossy().to_string())
+  }
+  fn show_column_stats(&mut self) {
+    let Some(&col_idx) = self.visible_columns().get(self.selected_column) else { return };
+    let rows = self.visible_rows();
+    let values: Vec<Cow<str>> = rows
+      .iter()
+      .filter_map(|&row_idx| self.data[row_idx].get(col_idx))
+      .filter_map(|cell| self.normalize(col_idx, cell))
+      .filter(|value| !value.trim().is_empty())
+      .collect();
+    let groups = group_ordered(values.iter(), |value| value.to_string());
+    let mut lines = vec![
+      format!("Column: {}", self.primary_header_cell(col_idx).trim()),
+      format!("Type: {}", infer_type(values.iter().map(|value| value.as_ref())).label()),
+      format!("Rows: {}", rows.len()),
+      format!("Non-empty: {}", values.len()),
+      format!("Distinct: {}", groups.len()),
+    ];
+    let numbers = NumberStats::of(values.iter().filter_map(|value| parse_number(value)));
+    match numbers {
+      Some(stats) if stats.count == values.len() => lines.extend([
+        format!("Min: {}", stats.min),
+        format!("Max: {}", stats.max),
+        format!("Mean: {}", stats.mean()),
+        format!("Sum: {}", stats.sum),
+      ]),
+      _ => {
+        let mut sorted: Vec<&str> = values.iter().map(|value| value.as_ref()).collect();
+        sorted.sort_by(|a, b| compare_cells(a, b));
+        if let (Some(min), Some(max)) = (sorted.first(), sorted.last()) {
+          lines.push(format!("Min: {}", display_cell(min)));
+          lines.push(format!("Max: {}", display_cell(max)));
+        }
+      }
+    }
+    let mut frequent: Vec<(String, usize)> =
+      groups.into_iter().map(|(value, rows)| (value, rows.len())).collect();
+    frequent.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    if !frequent.is_empty() {
+      lines.push(String::new());
+      lines.push("Most frequent:".to_string());
+      for (value, count) in frequent.into_iter().take(STATS_FREQUENT) {
+        lines.push(format!("  {:>6} × {}", count, display_cell(&value)));
+      }
+    }
+    self.popup_text = lines.join("\n");
+    self.popup_changed.clear();
+    self.popup_scroll = 0;
+    self.popup = Some(Popup::Stats);
+  }
+  fn show_records_preview(&mut self) {
+    let rows = self.export_rows();
+    let all_records = self.records_for_rows(&rows);
+    let records: Vec<_> =
+      self.dedup_records(all_records.clone()).into_iter().take(PREVIEW_RECORDS).collect();
+    self.popup_changed.clear();
+    self.popup_scroll = 0;
+    self.popup = Some(Popup::Records);
+    // Grouped, matrix and nested records are shown as written, without marking changed values
+    let shaped =
+      self.group_column.is_some() || self.matrix_output() || !self.key_paths().is_empty();
+    if shaped || records.is_empty() {
+      self.popup_text = match serde_json::to_string_pretty(&self.shape_records(records)) {
+        Ok(text) => text,
+        Err(err) => err.to_string(),
+      };
+      return;
+    }
+    let columns: HashMap<String, usize> = self
+      .export_columns()
+      .into_iter()
+      .map(|col_idx| (self.field_name(col_idx), col_idx))
+      .collect();
+    let mut originals = all_records.iter().zip(&rows);
+    let mut lines = vec!["[".to_string()];
+    for (i, record) in records.iter().enumerate() {
+      let row_idx = originals.find(|(original, _)| *original == record).map(|(_, &row)| row);
+      let fields = record.as_object().cloned().unwrap_or_default();
+      lines.push("  {".to_string());
+      for (j, (key, value)) in fields.iter().enumerate() {
+        let raw = row_idx.zip(columns.get(key)).map(|(row, &col)| self.data[row][col].as_str());
+        let shown = match value {
+          serde_json::Value::String(text) => text.clone(),
+          serde_json::Value::Null => String::new(),
+          other => other.to_string(),
+        };
+        if raw.is_some_and(|raw| raw != shown) {
+          self.popup_changed.insert(lines.len());
+        }
+        let comma = if j + 1 < fields.len() { "," } else { "" };
+        lines.push(format!("    {}: {}{}", serde_json::Value::from(key.as_str()), value, comma));
+      }
+      lines.push(format!("  }}{}", if i + 1 < records.len() { "," } else { "" }));
+    }
+    lines.push("]".to_string());
+    self.popup_text = lines.join("\n");
+  }
+  fn export_options(&self, filepath: &str) -> ExportOptions {
+    let stem = Path::new(filepath).file_stem().and_then(|s| s.to_str()).unwrap_or("export");
+    let table = match self.table_name.value().trim() {
+      "" => stem.to_string(),
+      name => name.to_string(),
     };
-    let filepath = format!("{}.json", filename);
-    if let Ok(mut file) = File::create(&filepath) {
-      let records = self.create_json_records();
-      let records = if self.deduplicate {
-        let mut seen = HashSet::new();
-        records.into_iter().filter(|rec| seen.insert(serde_json::to_string(rec).unwrap())).collect()
-      } else {
-        records
-      };
-      let json_array = serde_json::Value::Array(records);
-      if writeln!(file, "{}", to_string_pretty(&json_array).unwrap()).is_ok() {
-        self.export_toast = Some(format!("Exported to {}.json successfully", filename));
-        self.export_toast_time = Some(Instant::now());
+    let element = |input: &Input, default: &str| match input.value().trim() {
+      "" => default.to_string(),
+      name => name.to_string(),
+    };
+    ExportOptions {
+      table,
+      root: element(&self.xml_root, "records"),
+      record: element(&self.xml_record, "record"),
+      indent: self.json_indent(),
+      dialect: self.sql_dialect,
+      columns: self.export_columns().into_iter().map(|col_idx| self.field_name(col_idx)).collect(),
+    }
+  }
+  fn write_records(
+    &self,
+    filepath: &str,
+    mut records: Vec<serde_json::Value>,
+  ) -> Result<String, Box<dyn Error>> {
+    let mut message = format!("Exported to {} successfully", filepath);
+    if self.append {
+      let existing = fs::read_to_string(filepath)
+        .ok()
+        .and_then(|text| export::parse(self.export_format, text.trim_start_matches('\u{feff}')))
+        .map(export::from_matrix);
+      match existing {
+        Some(mut existing) => {
+          let added = records.len();
+          existing.append(&mut records);
+          records = existing;
+          message = format!("Appended {} records to {}", added, filepath);
+        }
+        _ => {
+          message = format!("{} not found or not a record array, created new file", filepath);
+        }
       }
     }
+    let records = self.shape_records(self.dedup_records(records));
+    let mut bytes =
+      export::serialize(self.export_format, &records, &self.export_options(filepath))?;
+    if self.export_format.is_text() {
+      bytes = export::encode_text(bytes, self.bom, self.crlf);
+    }
+    write_atomic(filepath, &bytes)?;
+    Ok(message)
   }
-  fn check_merge_options<T: Read + Seek>(&self, xlsx: &mut Xlsx<T>) -> Vec<(String, Vec<String>)> {
+  fn shape_records(&self, records: Vec<serde_json::Value>) -> Vec<serde_json::Value> {
+    if self.matrix_output() {
+      let keys: Vec<String> =
+        self.export_columns().into_iter().map(|col_idx| self.field_name(col_idx)).collect();
+      return export::to_matrix(&records, &keys);
+    }
+    let paths = self.key_paths();
+    if paths.is_empty() {
+      return records;
+    }
+    export::nest_keys(records, &paths)
+  }
+  // Custom keys like address.street become nested objects in the formats that can hold them
+  fn key_paths(&self) -> Vec<(String, Vec<String>)> {
+    let nests = matches!(
+      self.export_format,
+      ExportFormat::Json
+        | ExportFormat::Ndjson
+        | ExportFormat::Yaml
+        | ExportFormat::Toml
+        | ExportFormat::Xml
+    );
+    if !nests {
+      return Vec::new();
+    }
+    self
+      .export_columns()
+      .into_iter()
+      .filter(|&col_idx| self.custom_keys[col_idx].value().contains('.'))
+      .map(|col_idx| {
+        let key = self.field_name(col_idx);
+        let path: Vec<String> = key.split('.').map(String::from).collect();
+        (key, path)
+      })
+      .filter(|(_, path)| path.iter().all(|segment| !segment.is_empty()))
+      .collect()
+  }
+  fn dedup_records(&self, records: Vec<serde_json::Value>) -> Vec<serde_json::Value> {
+    if !self.deduplicate {
+      return records;
+    }
+    let keys: Vec<String> = self
+      .export_columns()
+      .into_iter()
+      .filter(|&col_idx| self.column_configs[col_idx].dedup_key)
+      .map(|col_idx| self.field_name(col_idx))
+      .collect();
+    let mut seen = HashSet::new();
+    records
+      .into_iter()
+      .filter(|rec| {
+        let key = if keys.is_empty() {
+          rec.clone()
+        } else {
+          serde_json::Value::from(keys.iter().map(|k| rec[k].clone()).collect::<Vec<_>>())
+        };
+        let key = if self.normalize_dedup { fold_value(key) } else { key };
+        seen.insert(key.to_string())
+      })
+      .collect()
+  }
+  fn check_merge_options(&self, source: &mut Source) -> Vec<MergeSheet> {
     let mut info = Vec::new();
+    if source.is_csv() {
+      return info;
+    }
     let primary_header = match self.data.get(self.first_row) {
       Some(row) => row,
       None => return info,
     };
     for (i, sheet_name) in self.sheets.iter().enumerate() {
-      if Some(i) == self.selected_sheet {
+      if Some(i) == self.selected_sheet || self.range_hints.contains_key(sheet_name) {
         continue;
       }
-      if let Ok(range) = xlsx.worksheet_range(sheet_name) {
-        if let Some(header_row) = range.rows().nth(self.first_row) {
-          let sheet_set: HashSet<_> =
-            header_row.iter().map(|s| s.to_string().trim().to_string()).collect();
-          let mutual: Vec<String> = primary_header
-            .iter()
-            .map(|s| s.trim().to_string())
-            .filter(|s| sheet_set.contains(s))
-            .collect();
-          if !mutual.is_empty() {
-            info.push((sheet_name.clone(), mutual));
-          }
+      if let Some(rows) = source.rows(sheet_name) {
+        let best = rows
+          .iter()
+          .take(HEADER_SCAN_ROWS.max(self.first_row + 1))
+          .enumerate()
+          .map(|(row_idx, _)| {
+            let header = self.sheet_header(&rows, row_idx);
+            (row_idx, pair_columns(primary_header, &header, self.merge_distance()))
+          })
+          .max_by_key(|(row_idx, pairs)| (pairs.len(), *row_idx == self.first_row));
+        if let Some((header_row, pairs)) = best.filter(|(_, pairs)| !pairs.is_empty()) {
+          let header = header_cells(&self.sheet_header(&rows, header_row));
+          info.push(MergeSheet {
+            name: sheet_name.clone(),
+            header_row,
+            header,
+            pairs,
+            include: true,
+          });
         }
       }
     }
     info
   }
-  fn perform_merge<T: Read + Seek>(&mut self, xlsx: &mut Xlsx<T>) {
-    let primary_header = match self.data.get(self.first_row) {
-      Some(row) => row,
-      None => return,
-    };
-
-    let mut common: HashSet<String> = primary_header.iter().map(|s| s.trim().to_string()).collect();
-    if let Some(ref info) = self.merge_info {
-      for (_, mutual) in info {
-        let sheet_set: HashSet<String> = mutual.iter().cloned().collect();
-        common = common.into_iter().filter(|s| sheet_set.contains(s)).collect();
-      }
+  fn undo_workspace(&mut self) {
+    let Some(workspace) = self.workspace_undo.pop() else { return };
+    let step = workspace.step;
+    let label = workspace.label;
+    let changed = self.swap_workspace(workspace);
+    self.workspace_redo.push(changed);
+    self.update_empty_columns();
+    self.step = step;
+    self.toast(format!("{} undone · Ctrl+Y to redo", label));
+  }
+  fn redo_workspace(&mut self) {
+    let Some(workspace) = self.workspace_redo.pop() else { return };
+    let label = workspace.label;
+    let previous = self.swap_workspace(workspace);
+    self.workspace_undo.push(previous);
+    self.update_empty_columns();
+    self.step = Step::ColSelect;
+    self.toast(format!("{} redone", label));
+  }
+  fn perform_merge(&mut self, source: &mut Source) {
+    if self.data.get(self.first_row).is_none() {
+      return;
     }
+    let backup = self.workspace("Merge");
+    let primary = self.primary_columns();
     let new_header: Vec<String> =
-      primary_header.iter().filter(|s| common.contains(&s.trim().to_string())).cloned().collect();
+      self.merge_columns().into_iter().filter(|s| !self.merge_dropped.contains(s)).collect();
+    // The source sheet of each row goes into an extra column, named uniquely
+    let provenance = self.merge_provenance.then(|| {
+      let mut name = SHEET_COLUMN.to_string();
+      while new_header.contains(&name) {
+        name.insert(0, '_');
+      }
+      name
+    });
     let mut merged_data = Vec::new();
-    merged_data.push(new_header.clone());
-    let mut merge_sheet = |sheet_name: &String| {
-      if let Ok(range) = xlsx.worksheet_range(sheet_name) {
-        let rows: Vec<_> = range.rows().collect();
-        if rows.len() <= self.first_row {
-          return;
-        }
-        let header_row = rows[self.first_row];
-        let header_map: HashMap<String, usize> = header_row
+    merged_data.push(new_header.iter().cloned().chain(provenance.clone()).collect());
+    let mut counts = Vec::new();
+    let mut merge_sheet = |sheet_name: &String,
+                           header: &[String],
+                           rows: &[Vec<String>],
+                           sources: Vec<Option<&String>>| {
+      if header.is_empty() {
+        counts.push(format!("{}: skipped, no header row", sheet_name));
+        return;
+      }
+      let (mut added, mut skipped) = (0, 0);
+      let header_map: HashMap<String, usize> =
+        header.iter().enumerate().map(|(idx, cell)| (cell.trim().to_string(), idx)).collect();
+      for row in rows {
+        let mut new_row: Vec<String> = sources
           .iter()
-          .enumerate()
-          .map(|(idx, cell)| (cell.to_string().trim().to_string(), idx))
+          .map(|col_name| match col_name.and_then(|col_name| header_map.get(col_name)) {
+            Some(&idx) => row.get(idx).cloned().unwrap_or_default(),
+            None => String::new(),
+          })
           .collect();
-        for row in rows.iter().skip(self.first_row + 1) {
-          let new_row: Vec<String> = new_header
-            .iter()
-            .map(|col_name| {
-              if let Some(&idx) = header_map.get(&col_name.trim().to_string()) {
-                row.get(idx).map(|s| s.to_string()).unwrap_or_default()
-              } else {
-                String::new()
-              }
-            })
-            .collect();
+        if new_row.iter().all(|cell| cell.trim().is_empty()) {
+          skipped += 1;
+        } else {
+          added += 1;
+          if provenance.is_some() {
+            new_row.push(sheet_name.clone());
+          }
           merged_data.push(new_row);
         }
       }
+      counts.push(match skipped {
+        0 => format!("{}: {} rows", sheet_name, added),
+        _ => format!("{}: {} rows, {} skipped", sheet_name, added, skipped),
+      });
     };
-    merge_sheet(&self.sheets[self.selected_sheet.unwrap()]);
-    if let Some(ref info) = self.merge_info {
-      for (sheet_name, _) in info {
-        merge_sheet(sheet_name);
+    // The primary sheet is taken as loaded, with its combined header and trimmed footer
+    if let Some(sheet_idx) = self.selected_sheet {
+      let sources = new_header.iter().map(|col| primary.contains(col).then_some(col)).collect();
+      let header = &self.data[self.first_row];
+      merge_sheet(&self.sheets[sheet_idx], header, &self.data[self.first_row + 1..], sources);
+    }
+    for sheet in self.merge_sheets() {
+      let sources = new_header.iter().map(|col| sheet.source(col, &primary)).collect();
+      if let Some(rows) = source.rows(&sheet.name) {
+        let header = self.sheet_header(&rows, sheet.header_row);
+        let data_rows = rows.get(sheet.header_row + 1..).unwrap_or_default();
+        merge_sheet(&sheet.name, &header, data_rows, sources);
       }
     }
+    self.merged_from = self
+      .selected_sheet
+      .map(|idx| (self.sheets[idx].clone(), self.first_row))
+      .into_iter()
+      .chain(self.merge_sheets().map(|sheet| (sheet.name.clone(), sheet.header_row)))
+      .collect();
+    self.reset_columns(new_header.len() + usize::from(provenance.is_some()));
     self.data = merged_data;
+    self.header_backup = None;
+    self.trimmed_rows.clear();
     self.sheets = vec!["[Merged]".to_string()];
     self.selected_sheet = Some(0);
     self.first_row = 0;
     self.export_filename = Input::default().with_value(self.get_default_filename());
+    self.workspace_undo.push(backup);
+    self.workspace_redo.clear();
+    self.toast(format!("Merged {} · Ctrl+Z to undo", counts.join(" · ")));
+  }
+  fn editing_preview(&self) -> bool {
+    self.editing_filter || self.editing_column_filter || self.editing_search
+  }
+  fn inspect_cell(&mut self, source: &mut Source) {
+    let Some(&row_idx) = self.page_rows().get(self.selected_row) else { return };
+    let Some(&col_idx) = self.visible_columns().get(self.selected_column) else { return };
+    // Rows only line up with the source until a merge, join or summary rebuilds them
+    self.cell_type = self
+      .selected_sheet
+      .filter(|_| self.workspace_undo.is_empty())
+      .and_then(|idx| source.cell_type(&self.sheets[idx], row_idx, col_idx));
+    self.popup_scroll = 0;
+    self.popup = Some(Popup::Cell);
+  }
+  fn summarize(&mut self) {
+    let Some(&group_col) = self.visible_columns().get(self.selected_column) else { return };
+    let backup = self.workspace("Summary");
+    let rows = self.visible_rows().len();
+    let name = self.primary_header_cell(group_col);
+    self.data = self.summary(group_col);
+    self.header_backup = None;
+    self.trimmed_rows.clear();
+    self.first_row = 0;
+    self.reset_columns(self.data[0].len());
+    self.columns.fill(ColumnState::Original);
+    self.row_filter = Input::default();
+    self.row_filter_column = None;
+    self.current_page = 0;
+    self.selected_row = 0;
+    self.selected_column = 0;
+    self.column_offset = 0;
+    self.update_empty_columns();
+    let filename = format!("{}_by_{}", self.export_filename.value(), normalize_text(&name));
+    self.export_filename = Input::default().with_value(filename);
+    self.workspace_undo.push(backup);
+    self.workspace_redo.clear();
+    self.toast(format!(
+      "Summarized {} rows into {} groups by {} · Ctrl+Z to undo",
+      rows,
+      self.data.len() - 1,
+      name.trim()
+    ));
+  }
+  fn start_join(&mut self, source: &mut Source) {
+    let current = self.selected_sheet.map(|idx| self.sheets[idx].clone());
+    let sheets: Vec<String> =
+      source.sheet_names().into_iter().filter(|name| Some(name) != current.as_ref()).collect();
+    if sheets.is_empty() || self.columns.is_empty() {
+      self.toast("No other sheet to join".to_string());
+      return;
+    }
+    let mut join = JoinState {
+      sheets,
+      sheet: 0,
+      rows: Vec::new(),
+      header_row: 0,
+      key: self.selected_column.min(self.columns.len() - 1),
+      other_key: 0,
+      field: 0,
+    };
+    self.load_join_sheet(&mut join, source);
+    self.join = Some(join);
+    self.step = Step::Join;
+  }
+  fn load_join_sheet(&self, join: &mut JoinState, source: &mut Source) {
+    join.rows = source.rows(&join.sheets[join.sheet]).unwrap_or_default();
+    // Prefer the first row naming the key column, then the first non-empty one
+    let key = normalize_text(&self.primary_header_cell(join.key));
+    join.header_row = join
+      .rows
+      .iter()
+      .position(|row| row.iter().any(|cell| normalize_text(cell) == key))
+      .or_else(|| join.rows.iter().position(|row| row.iter().any(|cell| !cell.trim().is_empty())))
+      .unwrap_or(0);
+    join.match_key(&self.primary_header_cell(join.key));
+  }
+  fn primary_header_cell(&self, col_idx: usize) -> String {
+    self.data.get(self.first_row).and_then(|row| row.get(col_idx)).cloned().unwrap_or_default()
+  }
+  fn handle_join(&mut self, key: KeyEvent, source: &mut Source) {
+    let Some(mut join) = self.join.take() else {
+      self.step = Step::ColSelect;
+      return;
+    };
+    match key.code {
+      Up | Down => join.field = navigate_index(join.field, 4, key.code),
+      Left | Right => {
+        let code = if key.code == Right { Down } else { Up };
+        match join.field {
+          0 => {
+            join.sheet = navigate_index(join.sheet, join.sheets.len(), code);
+            self.load_join_sheet(&mut join, source);
+          }
+          1 if !join.rows.is_empty() => {
+            join.header_row = navigate_index(join.header_row, join.rows.len(), code);
+            join.match_key(&self.primary_header_cell(join.key));
+          }
+          2 => {
+            join.key = navigate_index(join.key, self.columns.len(), code);
+            join.match_key(&self.primary_header_cell(join.key));
+          }
+          3 if !join.header().is_empty() => {
+            join.other_key = navigate_index(join.other_key, join.header().len(), code);
+          }
+          _ => {}
+        }
+      }
+      Enter if join.added_columns(&[]).is_empty() => {
+        self.toast(format!("'{}' has no columns to add", join.sheets[join.sheet]));
+      }
+      Enter => {
+        self.perform_join(join);
+        return;
+      }
+      _ => {}
+    }
+    self.join = Some(join);
+  }
+  fn perform_join(&mut self, join: JoinState) {
+    self.step = Step::ColSelect;
+    let backup = self.workspace("Join");
+    let Some(header) = self.data.get(self.first_row) else { return };
+    let added = join.added_columns(header);
+    let lookup = join.lookup();
+    let data_rows = &self.data[self.first_row + 1..];
+    let matched = join.matches(data_rows);
+    let total = data_rows.len();
+    let first_row = self.first_row;
+    for (row_idx, row) in self.data.iter_mut().enumerate() {
+      let other = row.get(join.key).and_then(|cell| lookup.get(&cell.trim().to_lowercase()));
+      let cells = added.iter().map(|(idx, name)| {
+        if row_idx == first_row {
+          name.clone()
+        } else if row_idx > first_row {
+          other.and_then(|other| other.get(*idx)).cloned().unwrap_or_default()
+        } else {
+          String::new()
+        }
+      });
+      let cells: Vec<String> = cells.collect();
+      row.extend(cells);
+    }
+    if let Some((_, original)) = &mut self.header_backup {
+      original.extend(added.iter().map(|(_, name)| name.clone()));
+    }
+    let count = added.len();
+    for row in &mut self.trimmed_rows {
+      row.resize(row.len() + count, String::new());
+    }
+    self.columns.extend(std::iter::repeat_n(ColumnState::Original, count));
+    self.column_configs.extend(std::iter::repeat_n(ColumnConfig::default(), count));
+    self.custom_keys.extend(std::iter::repeat_n(Input::default(), count));
+    self.column_filters.extend(std::iter::repeat_n(Input::default(), count));
+    self.undo_stack.clear();
+    self.redo_stack.clear();
+    self.update_empty_columns();
+    self.workspace_undo.push(backup);
+    self.workspace_redo.clear();
+    self.toast(format!(
+      "Joined {}: {} of {} rows matched · Ctrl+Z to undo",
+      join.sheets[join.sheet], matched, total
+    ));
   }
 }
 fn ui(f: &mut Frame, app: &mut App) {
   if let Some(time) = app.export_toast_time {
-    if time.elapsed().as_secs() >= 3 {
+    if time.elapsed().as_secs() >= app.toast_seconds {
       app.export_toast = None;
       app.export_toast_time = None;
     }
   }
-  let (header, content, footer) = layout(f);
+  let export_header = (app.step == Step::Export).then(|| app.export_header(f.area().width));
+  let header_height = export_header.as_ref().map_or(1, |lines| lines.len().max(1) as u16);
+  let (header, content, footer) = layout(f, header_height);
 
   match app.step {
     Step::MergePrompt => {
       let mut lines = vec![];
       if let Some(ref info) = app.merge_info {
-        lines
-          .push(format!("Merge data from other sheets?\n\n      Sheet        Mutual columns\n",));
-        for (sheet, mutual) in info {
-          lines.push(format!("{:<16} | {}", sheet, mutual.join(", ")));
+        lines.push(
+          "Merge data from other sheets?\n\n    Sheet            Header  Mutual columns\n"
+            .to_string(),
+        );
+        let primary = app.primary_columns();
+        for (i, sheet) in info.iter().enumerate() {
+          let marker = if i == app.merge_selected { "›" } else { " " };
+          let mutual: Vec<String> = primary
+            .iter()
+            .filter_map(|column| match sheet.pairs.get(column)? {
+              cell if cell == column => Some(column.clone()),
+              cell => Some(format!("{} = {}", column, cell)),
+            })
+            .collect();
+          lines.push(format!(
+            "{} {} {:<16} ←{:^4}→ | {}",
+            marker,
+            if sheet.include { "●" } else { "◯" },
+            sheet.name,
+            sheet.header_row,
+            mutual.join(", ")
+          ));
+        }
+        lines.push(format!(
+          "\n  Columns to keep · {}{}\n",
+          if app.merge_union { "all columns" } else { "columns shared by every sheet" },
+          if app.merge_provenance { format!(" · plus {}", SHEET_COLUMN) } else { String::new() }
+        ));
+        let merged = app.merge_columns();
+        let included: Vec<&MergeSheet> = info.iter().filter(|sheet| sheet.include).collect();
+        for (i, column) in app.merge_rows().iter().enumerate() {
+          let selected = info.len() + i == app.merge_selected;
+          let marker = if selected { "›" } else { " " };
+          let kept = if !merged.contains(column) {
+            "✗"
+          } else if app.merge_dropped.contains(column) {
+            "◯"
+          } else {
+            "●"
+          };
+          let sources: Vec<String> = included
+            .iter()
+            .enumerate()
+            .map(|(j, sheet)| {
+              let cell = sheet.source(column, &primary).map_or("—", |s| s.as_str());
+              if selected && j == app.merge_focus {
+                format!("[{}: {}]", sheet.name, cell)
+              } else {
+                format!(" {}: {} ", sheet.name, cell)
+              }
+            })
+            .collect();
+          lines.push(format!("{} {} {:<16} |{}", marker, kept, column, sources.join(" ")));
         }
       }
       let para = Paragraph::new(lines.join("\n"));
       f.render_widget(para, f.area());
     }
+    Step::Join => {
+      let mut lines = vec![];
+      if let Some(ref join) = app.join {
+        let header = join.header();
+        let primary = app.data.get(app.first_row);
+        let data_rows = &app.data[(app.first_row + 1).min(app.data.len())..];
+        let cell = |row: Option<&Vec<String>>, idx: usize| {
+          row.and_then(|row| row.get(idx)).map_or("—".to_string(), |cell| display_cell(cell))
+        };
+        let fields = [
+          ("Sheet", join.sheets[join.sheet].clone()),
+          ("Header row", join.header_row.to_string()),
+          ("Key here", cell(primary, join.key)),
+          ("Key there", cell(Some(&header), join.other_key)),
+        ];
+        lines.push("Join another sheet, keeping every row here\n".to_string());
+        for (i, (label, value)) in fields.iter().enumerate() {
+          let marker = if i == join.field { "›" } else { " " };
+          lines.push(format!("{} {:<12} ← {} →", marker, label, value));
+        }
+        let added: Vec<String> = join
+          .added_columns(primary.map_or(&[], |row| row))
+          .into_iter()
+          .map(|(_, name)| name)
+          .collect();
+        lines.push(format!("\n  Adds: {}", added.join(", ")));
+        lines.push(format!("  Matches: {} of {} rows", join.matches(data_rows), data_rows.len()));
+      }
+      f.render_widget(Paragraph::new(lines.join("\n")), f.area());
+    }
     Step::SheetSelect => {
       let label = "Search: ";
       let input_text = format!("{}{}", label, app.sheet_search.value());
@@ -431,50 +2207,63 @@ fn ui(f: &mut Frame, app: &mut App) {
       f.render_widget(Paragraph::new(input_text), header);
 
       let items: Vec<ListItem> = if app.sheet_search.value().is_empty() {
-        app
-          .sheets
-          .iter()
-          .enumerate()
-          .map(|(i, sheet)| {
-            ListItem::new(sheet.as_str()).style(if Some(i) == app.selected_sheet {
-              FOCUSED_STYLE
-            } else {
-              Style::default()
-            })
-          })
-          .collect()
+        app.sheets.iter().enumerate().map(|(i, _)| app.sheet_item(i)).collect()
       } else {
-        app
-          .matching_sheets
-          .iter()
-          .map(|&i| {
-            ListItem::new(app.sheets[i].as_str()).style(if Some(i) == app.selected_sheet {
-              FOCUSED_STYLE
-            } else {
-              Style::default()
-            })
-          })
-          .collect()
+        app.matching_sheets.iter().map(|&i| app.sheet_item(i)).collect()
       };
       let list = List::new(items);
       f.render_widget(list, content);
     }
     Step::RowTrim => {
-      let label = "First row number: ";
-      let input_text = format!("{}{}", label, app.row_input.value());
-      f.set_cursor_position((
-        header.x + (label.len() + app.row_input.value().len()) as u16,
-        header.y,
-      ));
-      f.render_widget(Paragraph::new(input_text), header);
+      let fields = [
+        ("First row number: ".to_string(), &app.row_input, RowTrimEdit::FirstRow),
+        ("   Last row: ".to_string(), &app.last_row_input, RowTrimEdit::LastRow),
+        (
+          format!(
+            "   Header rows: {}   Separator: ",
+            app.header_range().map_or(app.header_rows, |(_, rows)| rows)
+          ),
+          &app.header_separator,
+          RowTrimEdit::Separator,
+        ),
+      ];
+      let mut spans = Vec::new();
+      let mut cursor = 0;
+      for (label, input, edit) in fields {
+        cursor += visual_width(&label) + visual_width(input.value());
+        if edit == app.row_trim_edit {
+          f.set_cursor_position((header.x + cursor as u16, header.y));
+        }
+        let style = if edit == app.row_trim_edit { FOCUSED_STYLE } else { Style::default() };
+        spans.push(Span::raw(label));
+        spans.push(Span::styled(input.value().to_string(), style));
+      }
+      spans.push(Span::raw(format!(
+        "   Stop at empty row {}",
+        if app.stop_at_empty { "●" } else { "◯" }
+      )));
+      f.render_widget(Paragraph::new(Line::from(spans)), header);
 
-      let preview: Vec<String> = app
+      // Rows that would be dropped from the bottom are dimmed
+      let end = app
+        .header_range()
+        .and_then(|(row, header_rows)| app.data_end(row + header_rows - 1).ok())
+        .unwrap_or(app.data.len());
+      let preview: Vec<Line> = app
         .data
         .iter()
         .enumerate()
-        .map(|(i, row)| format!("{:<2} | {}", i, row.join(", ")))
+        .map(|(i, row)| {
+          let cells: Vec<String> = row.iter().map(|cell| display_cell(cell)).collect();
+          let text = format!("{:<2} | {}", i, cells.join(", "));
+          if i < end {
+            Line::raw(text)
+          } else {
+            Line::styled(text, Style::default().fg(Color::DarkGray))
+          }
+        })
         .collect();
-      let para = Paragraph::new(preview.join("\n"));
+      let para = Paragraph::new(preview);
       f.render_widget(para, content);
     }
     Step::ColSelect => {
@@ -487,14 +2276,16 @@ fn ui(f: &mut Frame, app: &mut App) {
             .enumerate()
             .map(|(i, col)| {
               let style = if i == app.selected_column { FOCUSED_STYLE } else { Style::default() };
+              let empty = if app.empty_columns.get(i) == Some(&true) { " (empty)" } else { "" };
               Line::styled(
                 format!(
-                  "  {} {}",
+                  "  {} {}{}",
                   match app.columns[i] {
                     ColumnState::Hidden => "◯",
                     _ => "●",
                   },
-                  col
+                  display_cell(col),
+                  empty
                 ),
                 style,
               )
@@ -502,17 +2293,22 @@ fn ui(f: &mut Frame, app: &mut App) {
             .collect()
         })
         .unwrap_or_default();
-      let text = columns.into_iter().collect::<Vec<Line>>();
-      f.set_cursor_position((0, f.area().y + 1 + app.selected_column as u16));
-      let para = Paragraph::new(text);
-      f.render_widget(para, content);
+      app.list_scroll = follow_scroll(app.list_scroll, app.selected_column, content.height);
+      f.set_cursor_position((0, content.y + (app.selected_column - app.list_scroll) as u16));
+      f.render_widget(Paragraph::new(columns).scroll((app.list_scroll as u16, 0)), content);
     }
     Step::Preview => {
       let chunks = Layout::default()
         .direction(Vertical)
-        .constraints([Constraint::Length(app.visible_columns().len() as u16), Constraint::Min(0)])
+        .constraints([
+          Constraint::Length(app.visible_columns().len() as u16),
+          Constraint::Min(0),
+          Constraint::Length(1),
+          Constraint::Length(1),
+        ])
         .split(f.area());
 
+      let column_types = if app.infer_types { app.column_types() } else { Vec::new() };
       let filter_info = app
         .visible_columns()
         .iter()
@@ -523,17 +2319,37 @@ fn ui(f: &mut Frame, app: &mut App) {
             .data
             .get(app.first_row)
             .and_then(|row| row.get(col_idx))
-            .map(|s| s.as_str())
-            .unwrap_or("Unknown");
+            .map_or("Unknown".to_string(), |s| display_cell(s));
+          let column_type =
+            column_types.get(i).map_or(String::new(), |t| format!(" [{}]", t.label()));
+          let column_filter = app.column_filters[col_idx].value().trim();
+          let column_filter = match ColumnFilter::parse(column_filter) {
+            Ok(Some(_)) => format!(" · where {}", column_filter),
+            Ok(None) => String::new(),
+            Err(_) => format!(" · where {} (invalid)", column_filter),
+          };
+          let sort = match app.sort {
+            Some((sorted, descending)) if sorted == col_idx => {
+              if descending {
+                " · sorted ↓"
+              } else {
+                " · sorted ↑"
+              }
+            }
+            _ => "",
+          };
           Line::styled(
             format!(
-              "  {} · {}",
+              "  {} · {}{}{}{}",
               match app.columns[col_idx] {
                 ColumnState::Original => "◯ Original",
                 ColumnState::NonEmpty => "● NonEmpty",
                 ColumnState::Hidden => "Hidden",
               },
-              column_name
+              column_name,
+              column_type,
+              column_filter,
+              sort
             ),
             style,
           )
@@ -541,59 +2357,122 @@ fn ui(f: &mut Frame, app: &mut App) {
         .collect::<Vec<_>>();
       f.set_cursor_position((0, app.selected_column as u16));
       f.render_widget(Paragraph::new(filter_info), chunks[0]);
-      f.render_widget(Paragraph::new(create_table(app, f.area().width).to_string()), chunks[1]);
+      if !app.visible_columns().is_empty() {
+        app.column_offset = app.column_offset.min(app.selected_column);
+        while app.selected_column >= app.column_offset + table_columns(app, chunks[1].width).len() {
+          app.column_offset += 1;
+        }
+      }
+      f.render_stateful_widget(
+        create_table(app, chunks[1].width),
+        chunks[1],
+        &mut table_state(app),
+      );
+      if app.editing_column_filter {
+        let col_idx = app.visible_columns()[app.selected_column];
+        let label = format!(
+          "Filter {} ({}): ",
+          display_cell(&app.data[app.first_row][col_idx]),
+          ColumnFilter::SYNTAX
+        );
+        let value = app.column_filters[col_idx].value();
+        f.set_cursor_position((
+          chunks[2].x + (visual_width(&label) + visual_width(value)) as u16,
+          chunks[2].y,
+        ));
+        f.render_widget(
+          Paragraph::new(Line::from(vec![
+            Span::styled(label, FOCUSED_STYLE),
+            Span::raw(value.to_string()),
+          ])),
+          chunks[2],
+        );
+      } else if app.editing_search {
+        let label = "Search: ";
+        f.set_cursor_position((
+          chunks[2].x + (visual_width(label) + visual_width(app.data_search.value())) as u16,
+          chunks[2].y,
+        ));
+        f.render_widget(
+          Paragraph::new(Line::from(vec![
+            Span::styled(label, FOCUSED_STYLE),
+            Span::raw(app.data_search.value().to_string()),
+          ])),
+          chunks[2],
+        );
+      } else if app.editing_filter {
+        let name = app
+          .row_filter_column
+          .and_then(|col_idx| app.data[app.first_row].get(col_idx))
+          .map_or(String::new(), |s| display_cell(s));
+        let label = format!("Filter {} contains: ", name);
+        f.set_cursor_position((
+          chunks[2].x + (visual_width(&label) + visual_width(app.row_filter.value())) as u16,
+          chunks[2].y,
+        ));
+        f.render_widget(
+          Paragraph::new(Line::from(vec![
+            Span::styled(label, FOCUSED_STYLE),
+            Span::raw(app.row_filter.value().to_string()),
+          ])),
+          chunks[2],
+        );
+      } else if let Some((name, value)) =
+        app.cursor_cell().map(|(name, value)| (name.to_string(), value.to_string()))
+      {
+        let line_count = value.lines().count();
+        let mut spans =
+          vec![Span::styled(format!("{}: ", name), FOCUSED_STYLE), Span::raw(display_cell(&value))];
+        if line_count > 1 {
+          spans.push(Span::styled(format!("  ({} lines, v to expand)", line_count), FOCUSED_STYLE));
+        }
+        f.render_widget(Paragraph::new(Line::from(spans)), chunks[2]);
+        if app.popup == Some(Popup::Cell) {
+          let area = centered_rect(f.area(), 60, 50);
+          let width = area.width.saturating_sub(2).max(1) as usize;
+          let lines: usize =
+            value.lines().map(|line| visual_width(line).div_ceil(width).max(1)).sum();
+          let height = area.height.saturating_sub(2) as usize;
+          app.popup_scroll = app.popup_scroll.min(lines.saturating_sub(height) as u16);
+          let title = match app.cell_type {
+            Some(cell_type) => format!(" {} · {} · ↑↓ scroll · Esc to close ", name, cell_type),
+            None => format!(" {} · ↑↓ scroll · Esc to close ", name),
+          };
+          let block = Block::default().borders(Borders::ALL).title(title);
+          f.render_widget(Clear, area);
+          f.render_widget(
+            Paragraph::new(value)
+              .block(block)
+              .wrap(Wrap { trim: false })
+              .scroll((app.popup_scroll, 0)),
+            area,
+          );
+        }
+      }
     }
     Step::Export => {
       let visible_columns = app.visible_columns();
       let name_col_width = visible_columns
         .iter()
-        .map(|&col_idx| visual_width(&app.data[app.first_row][col_idx]))
+        .map(|&col_idx| visual_width(&display_cell(&app.data[app.first_row][col_idx])))
         .max()
         .unwrap_or(20)
         .max(20)
         + 1;
 
-      let filename_style = app.input_style(
-        app.export_focus_row == 0 && app.export_edit == ExportEdit::FileName,
-        ExportEdit::FileName,
-      );
-      let dedup_style = app.input_style(
-        app.export_focus_row == 0 && app.export_edit == ExportEdit::Deduplicate,
-        ExportEdit::Deduplicate,
-      );
-      let dedup_box = if app.deduplicate { " ● " } else { " ◯ " };
-
-      let line0 = Line::from(vec![
-        Span::raw("Filename: "),
-        Span::styled(format!("{}", app.export_filename), filename_style),
-        Span::raw("   Deduplicate "),
-        Span::styled(dedup_box, dedup_style),
-      ]);
-      f.render_widget(Paragraph::new(line0), header);
+      if let Some(lines) = export_header {
+        f.render_widget(Paragraph::new(lines), header);
+      }
 
       let mut lines = vec![Line::raw("")];
       for (i, &col_idx) in visible_columns.iter().enumerate() {
         let config = &app.column_configs[col_idx];
-        let column_name = &app.data[app.first_row][col_idx];
+        let column_name = display_cell(&app.data[app.first_row][col_idx]);
         let is_selected = app.export_focus_row > 0 && i == (app.export_focus_row - 1);
-        let custom_key = if app.custom_keys[col_idx].value().is_empty() {
-          normalize_text(column_name)
-        } else {
-          normalize_text(app.custom_keys[col_idx].value())
-        };
-        let display_name = match app.export_edit {
-          ExportEdit::KeyStr if is_selected => format!("{}", custom_key),
-          _ => custom_key,
-        };
-        let display_prefix = match app.export_edit {
-          ExportEdit::Prefix if is_selected => format!("{}", config.prefix.value()),
-          _ => config.prefix.value().to_string(),
-        };
-        let display_postfix = match app.export_edit {
-          ExportEdit::Postfix if is_selected => format!("{}", config.postfix.value()),
-          _ => config.postfix.value().to_string(),
-        };
-        let spans = vec![
+        let display_name = app.field_name(col_idx);
+        let display_prefix = config.prefix.value();
+        let display_postfix = config.postfix.value();
+        let mut spans = vec![
           Span::styled(
             format!("{:<name_col_width$}", column_name),
             app.input_style(is_selected, ExportEdit::FileName),
@@ -614,10 +2493,29 @@ fn ui(f: &mut Frame, app: &mut App) {
             app.input_style(is_selected, ExportEdit::Postfix),
           ),
         ];
+        if config.bool_map {
+          spans.push(Span::raw(" [bool]"));
+        }
+        let mut extras = vec![Span::raw(" ".repeat(name_col_width))];
+        for (label, value, is_set, edit) in app.column_extras(col_idx) {
+          if is_selected {
+            extras.push(Span::styled(label, app.input_style(is_selected, ExportEdit::FileName)));
+            extras.push(Span::styled(value, app.input_style(is_selected, edit)));
+          } else if is_set {
+            spans.push(Span::raw(label));
+            spans.push(Span::raw(value.trim_end().to_string()));
+          }
+        }
         lines.push(Line::from(spans));
+        if is_selected {
+          lines.push(Line::from(extras));
+        }
       }
 
-      f.render_widget(Paragraph::new(lines), content);
+      let selected_line = app.export_focus_row;
+      app.list_scroll = follow_scroll(app.list_scroll, selected_line + 1, content.height);
+      app.list_scroll = follow_scroll(app.list_scroll, selected_line, content.height);
+      f.render_widget(Paragraph::new(lines).scroll((app.list_scroll as u16, 0)), content);
       if let Some(msg) = &app.export_toast {
         f.render_widget(Paragraph::new(msg.as_str()).alignment(Alignment::Center), footer);
       }
@@ -627,61 +2525,514 @@ fn ui(f: &mut Frame, app: &mut App) {
 
   let navigate_guide = "↑↓ to navigate";
   let toggle_guide = "Space to toggle";
-  let back_guide = "Ctrl+B to go back";
+  let back_guide = "Esc/Ctrl+B to go back";
   let quit_guide = "Ctrl+Q to quit";
+  let clear_guide = "Ctrl+U to clear";
   let export_guide = "Enter to export";
 
   let footer_text = if let Some(msg) = &app.export_toast {
     msg.to_string()
   } else {
     match app.step {
-      Step::SheetSelect => format!("{} · {}", navigate_guide, quit_guide),
-      Step::RowTrim => format!("{} · {}", back_guide, quit_guide),
+      Step::SheetSelect => format!("{} · {} · {}", navigate_guide, clear_guide, quit_guide),
+      Step::RowTrim => format!(
+        "↑↓ header rows · Tab next field · e stop at empty row · {} · {} · {}",
+        back_guide, clear_guide, quit_guide
+      ),
       Step::ColSelect => {
-        format!("{} · {} · 'a' to toggle all · {}", navigate_guide, toggle_guide, quit_guide)
+        format!(
+          "{} · Home/End/PgUp/PgDn · {} · 'a' toggle all · 'i' invert · 'e' hide empty · J join · Ctrl+S/L save/load profile · {}",
+          navigate_guide, toggle_guide, quit_guide
+        )
+      }
+      Step::Preview if !app.data_search.value().is_empty() => {
+        let matches = app.search_matches();
+        let cursor = app.cursor_position();
+        let current = matches.iter().position(|&m| m == cursor).map_or(0, |i| i + 1);
+        format!(
+          "Search '{}' · match {}/{} · n/N next/previous · / new search · {}",
+          app.data_search.value(),
+          current,
+          matches.len(),
+          export_guide
+        )
       }
       Step::Preview => format!(
-        "{} · {} · Page ←{}/{}→ · Rows/page: -{}+ · {}",
+        "{} · {} · j/k row · x mark row · X {} marked · v cell · i stats · g group · / search · f filter · F where · s/S sort · w {} · t types · o {} · ←{}/{}→ · ⇧←→ columns · -{}+ rows · {}",
         navigate_guide,
         toggle_guide,
+        if app.keep_marked { "keeping" } else { "excluding" },
+        app.table_layout.label(),
+        if app.any_non_empty { "any" } else { "all" },
         app.current_page + 1,
         app.total_pages().max(1),
         app.rows_per_page,
         export_guide
       ),
-      Step::Export => format!("{} · Tab to cycle fields · {}", navigate_guide, export_guide),
-      Step::MergePrompt => "y/n".to_string(),
+      Step::Export if app.export_edit == ExportEdit::DateFormat => {
+        format!("Date format uses strftime, e.g. %d/%m/%Y or %b %-d %Y · {}", export_guide)
+      }
+      Step::Export if app.export_edit == ExportEdit::Template => {
+        format!("Template placeholders: {} · {}", TEMPLATE_PLACEHOLDERS, export_guide)
+      }
+      Step::Export => format!(
+        "{} · Tab to cycle fields · Ctrl+P preview · Ctrl+C copy · Ctrl+Z/Y undo/redo · Ctrl+R reset · {} records · {}",
+        navigate_guide,
+        app.export_rows().len(),
+        export_guide
+      ),
+      Step::Join => {
+        format!("{} · ←→ to change · Enter to join · {} · {}", navigate_guide, back_guide, quit_guide)
+      }
+      Step::MergePrompt => {
+        format!(
+          "{} · {} · ←→ header row or pairing · Tab sheet · f fuzzy · u union · s {} column · y to merge · n to skip",
+          navigate_guide, toggle_guide, SHEET_COLUMN
+        )
+      }
     }
   };
   f.render_widget(Paragraph::new(footer_text).alignment(Alignment::Center), footer);
+  let popup_title = match app.popup {
+    Some(Popup::Records) if !app.popup_changed.is_empty() => Some(format!(
+      " First {} records · transformed values in cyan · ↑↓ scroll · Esc to close ",
+      PREVIEW_RECORDS
+    )),
+    Some(Popup::Records) => {
+      Some(format!(" First {} records · ↑↓ scroll · Esc to close ", PREVIEW_RECORDS))
+    }
+    Some(Popup::Stats) => Some(" Column stats · ↑↓ scroll · Esc to close ".to_string()),
+    Some(Popup::Confirm) => Some(" Export? · Enter to confirm · Esc to cancel ".to_string()),
+    Some(Popup::ConfirmReset) => {
+      Some(" Reset export settings? · Enter to confirm · Esc to cancel ".to_string())
+    }
+    _ => None,
+  };
+  if let Some(title) = popup_title {
+    let area = centered_rect(f.area(), 70, 70);
+    let block = Block::default().borders(Borders::ALL).title(title);
+    f.render_widget(Clear, area);
+    let lines: Vec<Line> = app
+      .popup_text
+      .lines()
+      .enumerate()
+      .map(|(i, line)| {
+        if app.popup_changed.contains(&i) {
+          Line::styled(line, Style::default().fg(Color::Cyan))
+        } else {
+          Line::raw(line)
+        }
+      })
+      .collect();
+    f.render_widget(Paragraph::new(lines).block(block).scroll((app.popup_scroll, 0)), area);
+  }
+}
+
+fn render_exporting(f: &mut Frame, done: usize, total: usize) {
+  let area = centered_rect(f.area(), 60, 100);
+  let area =
+    Rect { y: area.y + area.height.saturating_sub(3) / 2, height: area.height.min(3), ..area };
+  let block = Block::default().borders(Borders::ALL).title(" Exporting… · Esc to cancel ");
+  let gauge = Gauge::default()
+    .block(block)
+    .gauge_style(FOCUSED_STYLE)
+    .label(format!("{}/{} records", done, total))
+    .ratio(if total == 0 { 1.0 } else { done as f64 / total as f64 });
+  f.render_widget(Clear, area);
+  f.render_widget(gauge, area);
+}
+
+fn cancel_requested() -> bool {
+  while let Ok(true) = poll(Duration::ZERO) {
+    if let Ok(Key(key)) = event::read() {
+      if key.code == Esc {
+        return true;
+      }
+    }
+  }
+  false
+}
+
+fn render_loading(f: &mut Frame, sheet: &str, done: usize, total: usize) {
+  let area = centered_rect(f.area(), 60, 100);
+  let area =
+    Rect { y: area.y + area.height.saturating_sub(3) / 2, height: area.height.min(3), ..area };
+  let block = Block::default().borders(Borders::ALL).title(format!(" Loading {}… ", sheet));
+  let gauge = if total == 0 {
+    Gauge::default().block(block).label("reading workbook…").ratio(0.0)
+  } else {
+    Gauge::default()
+      .block(block)
+      .gauge_style(FOCUSED_STYLE)
+      .label(format!("{}/{} rows", done, total))
+      .ratio(done as f64 / total as f64)
+  };
+  f.render_widget(Clear, area);
+  f.render_widget(gauge, area);
+}
+
+fn fold_value(value: serde_json::Value) -> serde_json::Value {
+  match value {
+    serde_json::Value::String(s) => serde_json::Value::String(normalize_text(s.trim())),
+    serde_json::Value::Array(items) => items.into_iter().map(fold_value).collect(),
+    serde_json::Value::Object(map) => {
+      serde_json::Value::Object(map.into_iter().map(|(k, v)| (k, fold_value(v))).collect())
+    }
+    other => other,
+  }
+}
+
+fn write_atomic(filepath: &str, bytes: &[u8]) -> std::io::Result<()> {
+  let tmp_path = format!("{}.tmp", filepath);
+  let written = File::create(&tmp_path)
+    .and_then(|mut file| file.write_all(bytes))
+    .and_then(|_| fs::rename(&tmp_path, filepath));
+  if written.is_err() {
+    let _ = fs::remove_file(&tmp_path);
+  }
+  written
+}
+
+fn restore_terminal() {
+  let _ = disable_raw_mode();
+  let _ = execute!(stdout(), LeaveAlternateScreen, DisableMouseCapture);
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
   let args: Vec<String> = args().collect();
-  if args.len() != 2 {
-    println!("Usage: {} <excel_file>", args[0]);
+  let cli = match Cli::parse(&args[1..]) {
+    Ok(cli) => cli,
+    Err(message) => {
+      eprintln!("{}", message);
+      process::exit(2);
+    }
+  };
+  if cli.help {
+    print!("{}", cli::HELP);
+    return Ok(());
+  }
+  if cli.version {
+    println!("xltool {}", env!("CARGO_PKG_VERSION"));
+    return Ok(());
+  }
+  if cli.headless() {
+    return run_batch(&cli);
+  }
+  let recent = if cli.use_config { config::recent_files() } else { Vec::new() };
+  if cli.files.is_empty() && recent.is_empty() {
+    print!("{}", cli::HELP);
     return Ok(());
   }
-  let mut xlsx = open_workbook(&args[1])?;
-  let mut app = App::new(&mut xlsx, &args[1]);
+  let default_hook = panic::take_hook();
+  panic::set_hook(Box::new(move |info| {
+    restore_terminal();
+    default_hook(info);
+  }));
   enable_raw_mode()?;
   let mut stdout = stdout();
-  execute!(stdout, EnterAlternateScreen)?;
+  execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
   let backend = CrosstermBackend::new(stdout);
   let mut terminal = Terminal::new(backend)?;
+  let result = if cli.files.is_empty() {
+    match pick_recent_file(&mut terminal, &recent) {
+      Ok(Some(file)) => open_file(&mut terminal, &[file], cli.use_config),
+      other => other.map(|_| ()),
+    }
+  } else {
+    open_file(&mut terminal, &cli.files, cli.use_config)
+  };
+  restore_terminal();
+  result
+}
+
+fn run_batch(cli: &Cli) -> Result<(), Box<dyn Error>> {
+  if cli.files.is_empty() {
+    eprintln!("No input files");
+    process::exit(2);
+  }
+  if cli.out.is_some() && cli.files.len() > 1 {
+    eprintln!("--out needs exactly one input file");
+    process::exit(2);
+  }
+  let mut failed = 0;
+  for file in &cli.files {
+    match export_file(file, cli) {
+      Ok(message) => println!("{}: {}", file, message),
+      Err(message) => {
+        failed += 1;
+        eprintln!("{}: {}", file, message);
+      }
+    }
+  }
+  println!("{} exported, {} failed", cli.files.len() - failed, failed);
+  if failed > 0 {
+    process::exit(1);
+  }
+  Ok(())
+}
+
+fn export_file(file: &str, cli: &Cli) -> Result<String, String> {
+  let mut source = Source::open(file).map_err(|err| err.to_string())?;
+  let mut app = App::new(&source, file, cli.use_config);
+  if let Some(sheet) = &cli.sheet {
+    let idx = app.sheets.iter().position(|name| name == sheet);
+    app.selected_sheet = Some(idx.ok_or(format!("No sheet named '{}'", sheet))?);
+  }
+  if !app.load_sheet(&mut source) {
+    return Err(app.export_toast.take().unwrap_or_default());
+  }
+  // A sheet cut at XLRS_MAX_ROWS would export only part of its rows, so the file fails
+  if let Some(message) = app.export_toast.take() {
+    return Err(message);
+  }
+  app.configure_headless(cli)?;
+  app.export(cli.strict)
+}
+
+fn pick_recent_file(
+  terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+  recent: &[String],
+) -> Result<Option<String>, Box<dyn Error>> {
+  let mut selected = 0;
+  loop {
+    terminal.draw(|f| {
+      let (header, content, footer) = layout(f, 1);
+      f.render_widget(Paragraph::new("Recent files"), header);
+      let items: Vec<ListItem> = recent
+        .iter()
+        .enumerate()
+        .map(|(i, file)| {
+          ListItem::new(file.as_str()).style(if i == selected {
+            FOCUSED_STYLE
+          } else {
+            Style::default()
+          })
+        })
+        .collect();
+      f.render_widget(List::new(items), content);
+      f.render_widget(
+        Paragraph::new("↑↓ to navigate · Enter to open · Esc/Ctrl+Q to quit")
+          .alignment(Alignment::Center),
+        footer,
+      );
+    })?;
+    if let Key(key) = event::read()? {
+      match key.code {
+        Up | Down => selected = navigate_index(selected, recent.len(), key.code),
+        Enter => return Ok(Some(recent[selected].clone())),
+        Esc => return Ok(None),
+        Char('q') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(None),
+        _ => {}
+      }
+    }
+  }
+}
+
+fn open_file(
+  terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+  files: &[String],
+  use_config: bool,
+) -> Result<(), Box<dyn Error>> {
+  let mut source = Source::open_all(files)?;
+  if use_config {
+    for file in files {
+      let _ = config::remember_file(file);
+    }
+  }
+  let file = &files[0];
+  let mut app = App::new(&source, file, use_config);
+  if source.is_csv() && app.load_sheet(&mut source) {
+    app.step = Step::RowTrim;
+  }
+  if Profile::path(file).exists() {
+    app.toast("Found a saved profile · Ctrl+L to apply it".to_string());
+  }
+  run(terminal, &mut app, &mut source)
+}
 
+fn run(
+  terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+  app: &mut App,
+  source: &mut Source,
+) -> Result<(), Box<dyn Error>> {
   loop {
     if poll(Duration::from_millis(100))? {
-      if let Key(key) = event::read()? {
-        if app.handle_key(key, key.modifiers, &mut xlsx) {
-          break;
-        }
+      match event::read()? {
+        Key(key) if app.handle_key(key, key.modifiers, source) => break,
+        Mouse(mouse) => app.handle_mouse(mouse, terminal.size()?.width),
+        _ => {}
       }
     }
-    terminal.draw(|f| ui(f, &mut app))?;
+    terminal.draw(|f| ui(f, app))?;
     terminal.show_cursor()?;
+    if app.pending_load {
+      app.pending_load = false;
+      let sheet = app.selected_sheet.map(|i| app.sheets[i].clone()).unwrap_or_default();
+      terminal.draw(|f| render_loading(f, &sheet, 0, 0))?;
+      let loaded = app.load_sheet_with_progress(source, &mut |done, total| {
+        let _ = terminal.draw(|f| render_loading(f, &sheet, done, total));
+      });
+      if loaded {
+        app.step = Step::RowTrim;
+      }
+    }
+    if app.pending_export {
+      app.pending_export = false;
+      app.export_sheet(&mut |done, total| {
+        let _ = terminal.draw(|f| render_exporting(f, done, total));
+        !cancel_requested()
+      });
+    }
   }
-  disable_raw_mode()?;
-  execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn load(rows: Vec<Vec<&str>>) -> App {
+    let rows = rows.into_iter().map(|row| row.into_iter().map(String::from).collect()).collect();
+    let mut source = Source::Csv { name: "test".to_string(), rows };
+    let mut app = App::new(&source, "test.csv", false);
+    assert!(app.load_sheet(&mut source));
+    app.rows_per_page = 5;
+    app
+  }
+
+  #[test]
+  fn total_pages_of_empty_data_is_zero() {
+    let mut app = load(vec![vec!["id"]]);
+    assert_eq!(app.total_pages(), 0);
+    app.rows_per_page = 0;
+    assert_eq!(app.total_pages(), 0);
+  }
+
+  #[test]
+  fn total_pages_of_single_row_is_one() {
+    let mut app = load(vec![vec!["id"], vec!["1"]]);
+    assert_eq!(app.total_pages(), 1);
+    app.rows_per_page = 0;
+    assert_eq!(app.total_pages(), 1);
+  }
+
+  #[test]
+  fn total_pages_of_exact_multiple_has_no_extra_page() {
+    let mut rows = vec![vec!["id"]];
+    rows.extend(std::iter::repeat_n(vec!["1"], 10));
+    let mut app = load(rows);
+    assert_eq!(app.total_pages(), 2);
+    app.rows_per_page = 0;
+    assert_eq!(app.total_pages(), 10);
+  }
+
+  #[test]
+  fn ragged_rows_are_padded_to_the_widest_row() {
+    let mut app = load(vec![
+      vec!["id", "name"],
+      vec!["1"],
+      vec!["2", "b", "extra", "more"],
+      vec!["3", "c", "x"],
+    ]);
+    assert!(app.data.iter().all(|row| row.len() == 4));
+    let columns = [0, 1, 2, 3];
+    let record = |app: &App, row_idx| {
+      app.create_record(row_idx, &columns, &[None, None, None, None], &[None; 4])
+    };
+    assert_eq!(
+      record(&app, 1),
+      serde_json::json!({ "id": "1", "name": "", "column_3": "", "column_4": "" })
+    );
+    assert_eq!(
+      record(&app, 2),
+      serde_json::json!({ "id": "2", "name": "b", "column_3": "extra", "column_4": "more" })
+    );
+    app.null_tokens = Input::default().with_value("\"\"".to_string());
+    assert_eq!(app.normalize(1, &app.data[1][1]), None);
+    assert_eq!(
+      record(&app, 3),
+      serde_json::json!({ "id": "3", "name": "c", "column_3": "x", "column_4": null })
+    );
+    assert_eq!(app.normalize(2, &app.data[2][2]).as_deref(), Some("extra"));
+  }
+
+  #[test]
+  fn null_tokens_win_over_bool_map_and_typed_values() {
+    let mut app = load(vec![vec!["flag", "n"], vec!["no", "N/A"], vec!["yes", "5"]]);
+    app.null_tokens = Input::default().with_value("N/A, no".to_string());
+    app.column_configs[0].bool_map = true;
+    let types = [None, Some(ColumnType::Int)];
+    let record = |app: &App, row_idx| app.create_record(row_idx, &[0, 1], &[None, None], &types);
+    assert_eq!(record(&app, 1), serde_json::json!({ "flag": null, "n": null }));
+    assert_eq!(record(&app, 2), serde_json::json!({ "flag": true, "n": 5 }));
+    // A column's own tokens replace the global ones for that column only
+    app.column_configs[1].nulls = Input::default().with_value("-".to_string());
+    assert_eq!(record(&app, 1), serde_json::json!({ "flag": null, "n": "N/A" }));
+  }
+
+  #[test]
+  fn max_rows_caps_the_rows_read_from_a_sheet() {
+    let rows = (0..10).map(|i| vec![i.to_string()]).collect();
+    let mut source = Source::Csv { name: "test".to_string(), rows };
+    let mut app = App::new(&source, "test.csv", false);
+    app.max_rows = Some(4);
+    assert!(app.load_sheet(&mut source));
+    assert_eq!(app.data.len(), 4);
+    assert!(app.export_toast.is_some());
+    app.max_rows = Some(10);
+    assert!(app.load_sheet(&mut source));
+    assert_eq!(app.data.len(), 10);
+  }
+
+  #[test]
+  fn snapshots_differ_on_any_column_setting() {
+    let mut app = load(vec![vec!["id", "name"], vec!["1", "a"]]);
+    let before = app.snapshot();
+    assert!(before == app.snapshot());
+    app.column_configs[1].number_format = NumberFormat::Integer;
+    assert!(before != app.snapshot());
+    app.column_configs[1] = ColumnConfig::default();
+    app.marked_rows.extend([1, 2, 3]);
+    let marked = app.snapshot();
+    app.marked_rows = [3, 2, 1].into_iter().collect();
+    assert!(marked == app.snapshot());
+  }
+
+  #[test]
+  fn prefixed_number_column_stays_numeric() {
+    let mut app = load(vec![vec!["price"], vec!["10"]]);
+    app.column_configs[0].prefix = Input::default().with_value("$".to_string());
+    app.column_configs[0].number_format = NumberFormat::Integer;
+    let record = |app: &App| app.create_record(1, &[0], &[None], &[None]);
+    assert_eq!(record(&app), serde_json::json!({ "price": 10 }));
+    app.column_configs[0].affix_typed = true;
+    assert_eq!(record(&app), serde_json::json!({ "price": "$10" }));
+  }
+
+  #[test]
+  fn range_rules_read_grouped_numbers() {
+    let mut app = load(vec![vec!["amount"], vec!["1,500"], vec!["2,500"]]);
+    app.columns.fill(ColumnState::Original);
+    app.column_configs[0].max = Input::default().with_value("2,000".to_string());
+    assert_eq!(app.validation_errors(), Ok(vec!["1 rows failed range on amount".to_string()]));
+  }
+
+  #[test]
+  fn records_preview_nests_dotted_keys() {
+    let mut app = load(vec![vec!["street"], vec!["Main St"]]);
+    app.columns.fill(ColumnState::Original);
+    app.custom_keys[0] = Input::default().with_value("address.street".to_string());
+    app.show_records_preview();
+    assert!(app.popup_text.contains("\"address\": {"), "{}", app.popup_text);
+  }
+
+  #[test]
+  fn typed_filename_wins_over_the_template() {
+    let mut app = load(vec![vec!["id"], vec!["1"]]);
+    app.filename_template = Input::default().with_value("{sheet}_{date}".to_string());
+    assert!(app.export_name().starts_with("test_"));
+    app.step = Step::Export;
+    app.handle_export(KeyEvent::new(Char('x'), KeyModifiers::NONE));
+    assert_eq!(app.export_name(), "testx");
+  }
+}
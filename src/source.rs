@@ -0,0 +1,321 @@
+use calamine::{open_workbook_auto, Cell, Data, DataRef, Range, Reader, Sheets};
+use chrono::NaiveTime;
+use std::{error::Error, fs::File, io::BufReader, path::Path};
+
+const PROGRESS_CHUNK: usize = 10_000;
+const FILE_SEPARATOR: &str = " › ";
+
+#[derive(Clone)]
+pub struct NamedRange {
+  pub name: String,
+  pub sheet: String,
+  area: Option<((u32, u32), (u32, u32))>,
+}
+
+impl NamedRange {
+  pub fn hint(&self) -> String {
+    match self.area {
+      Some(_) => format!("range in {}", self.sheet),
+      None => format!("table in {}", self.sheet),
+    }
+  }
+}
+
+pub enum Source {
+  Workbook(Box<Sheets<BufReader<File>>>, Vec<NamedRange>),
+  Csv { name: String, rows: Vec<Vec<String>> },
+  // Several files opened together, with sheet names prefixed by their file
+  Files(Vec<(String, Source)>, Vec<NamedRange>),
+}
+
+impl Source {
+  pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+    let path_ref = Path::new(path);
+    let extension = path_ref.extension().and_then(|ext| ext.to_str()).map(str::to_lowercase);
+    let delimiter = match extension.as_deref() {
+      Some("csv") => b',',
+      Some("tsv" | "tab") => b'\t',
+      _ => {
+        let mut workbook = open_workbook_auto(path)?;
+        let ranges = named_ranges(&mut workbook);
+        return Ok(Source::Workbook(Box::new(workbook), ranges));
+      }
+    };
+    let mut reader = csv::ReaderBuilder::new()
+      .has_headers(false)
+      .flexible(true)
+      .delimiter(delimiter)
+      .from_path(path_ref)?;
+    let rows = reader
+      .records()
+      .map(|record| record.map(|r| r.iter().map(|cell| cell.to_string()).collect()))
+      .collect::<Result<_, _>>()?;
+    let name = path_ref.file_stem().and_then(|s| s.to_str()).unwrap_or(path).to_string();
+    Ok(Source::Csv { name, rows })
+  }
+  pub fn open_all(paths: &[String]) -> Result<Self, Box<dyn Error>> {
+    if let [path] = paths {
+      return Source::open(path);
+    }
+    let file_name = |path: &String| {
+      Path::new(path).file_name().map_or(path.clone(), |name| name.to_string_lossy().to_string())
+    };
+    let mut files = Vec::new();
+    let mut ranges = Vec::new();
+    for path in paths {
+      let source = Source::open(path).map_err(|err| format!("{}: {}", path, err))?;
+      // Sheets are labelled by file name unless two files share one
+      let label = match paths.iter().filter(|other| file_name(other) == file_name(path)).count() {
+        1 => file_name(path),
+        _ => path.clone(),
+      };
+      let prefix = |name: &str| format!("{}{}{}", label, FILE_SEPARATOR, name);
+      ranges.extend(source.named_ranges().iter().map(|range| NamedRange {
+        name: prefix(&range.name),
+        sheet: prefix(&range.sheet),
+        area: range.area,
+      }));
+      files.push((label, source));
+    }
+    Ok(Source::Files(files, ranges))
+  }
+  pub fn is_csv(&self) -> bool {
+    matches!(self, Source::Csv { .. })
+  }
+  pub fn sheet_names(&self) -> Vec<String> {
+    match self {
+      Source::Workbook(workbook, ranges) => {
+        let mut names = workbook.sheet_names();
+        names.extend(ranges.iter().map(|range| range.name.clone()));
+        names
+      }
+      Source::Csv { name, .. } => vec![name.clone()],
+      Source::Files(files, _) => files
+        .iter()
+        .flat_map(|(path, source)| {
+          source
+            .sheet_names()
+            .into_iter()
+            .map(move |name| format!("{}{}{}", path, FILE_SEPARATOR, name))
+        })
+        .collect(),
+    }
+  }
+  pub fn named_ranges(&self) -> &[NamedRange] {
+    match self {
+      Source::Workbook(_, ranges) | Source::Files(_, ranges) => ranges,
+      Source::Csv { .. } => &[],
+    }
+  }
+  pub fn rows(&mut self, sheet: &str) -> Option<Vec<Vec<String>>> {
+    self.rows_with_progress(sheet, None, &mut |_, _| {})
+  }
+  // Reads at most limit rows; streamed xlsx and xlsb sheets stop reading the file there
+  pub fn rows_with_progress(
+    &mut self,
+    sheet: &str,
+    limit: Option<usize>,
+    progress: &mut dyn FnMut(usize, usize),
+  ) -> Option<Vec<Vec<String>>> {
+    let limit = limit.unwrap_or(usize::MAX);
+    match self {
+      Source::Workbook(workbook, ranges) => match ranges.iter().find(|range| range.name == sheet) {
+        Some(NamedRange { area: Some((start, end)), sheet, .. }) => {
+          let range = workbook.worksheet_range(sheet).ok()?.range(*start, *end);
+          Some(range_rows(&range, limit, progress))
+        }
+        Some(NamedRange { area: None, .. }) => {
+          let Sheets::Xlsx(xlsx) = workbook.as_mut() else { return None };
+          let table = xlsx.table_by_name(sheet).ok()?;
+          let mut rows = vec![table.columns().to_vec()];
+          rows.extend(range_rows(table.data(), limit.saturating_sub(1), progress));
+          Some(rows)
+        }
+        None => {
+          let streamed = match workbook.as_mut() {
+            Sheets::Xlsx(xlsx) => xlsx.worksheet_cells_reader(sheet).ok().and_then(|mut reader| {
+              let total = reader.dimensions().end.0.saturating_sub(reader.dimensions().start.0);
+              cell_rows(&mut || reader.next_cell().ok(), total as usize + 1, limit, progress)
+            }),
+            Sheets::Xlsb(xlsb) => xlsb.worksheet_cells_reader(sheet).ok().and_then(|mut reader| {
+              let total = reader.dimensions().end.0.saturating_sub(reader.dimensions().start.0);
+              cell_rows(&mut || reader.next_cell().ok(), total as usize + 1, limit, progress)
+            }),
+            _ => None,
+          };
+          match streamed {
+            Some(rows) => Some(rows),
+            None => Some(range_rows(&workbook.worksheet_range(sheet).ok()?, limit, progress)),
+          }
+        }
+      },
+      Source::Csv { name, rows } => {
+        (name == sheet).then(|| rows.iter().take(limit).cloned().collect())
+      }
+      Source::Files(files, _) => files.iter_mut().find_map(|(path, source)| {
+        let name = sheet.strip_prefix(path.as_str())?.strip_prefix(FILE_SEPARATOR)?;
+        source.rows_with_progress(name, Some(limit), progress)
+      }),
+    }
+  }
+  // The calamine type of a cell, addressed like the rows returned for the sheet
+  pub fn cell_type(&mut self, sheet: &str, row: usize, col: usize) -> Option<&'static str> {
+    match self {
+      Source::Workbook(workbook, ranges) => {
+        let cell = match ranges.iter().find(|range| range.name == sheet) {
+          Some(NamedRange { area: Some((start, end)), sheet, .. }) => {
+            workbook.worksheet_range(sheet).ok()?.range(*start, *end).get((row, col))?.clone()
+          }
+          Some(NamedRange { area: None, .. }) => {
+            let Sheets::Xlsx(xlsx) = workbook.as_mut() else { return None };
+            let table = xlsx.table_by_name(sheet).ok()?;
+            match row.checked_sub(1) {
+              Some(row) => table.data().get((row, col))?.clone(),
+              None => Data::String(table.columns().get(col)?.clone()),
+            }
+          }
+          None => workbook.worksheet_range(sheet).ok()?.get((row, col))?.clone(),
+        };
+        Some(data_type(&cell))
+      }
+      Source::Csv { .. } => Some("String"),
+      Source::Files(files, _) => files.iter_mut().find_map(|(path, source)| {
+        let name = sheet.strip_prefix(path.as_str())?.strip_prefix(FILE_SEPARATOR)?;
+        source.cell_type(name, row, col)
+      }),
+    }
+  }
+}
+
+fn data_type(cell: &Data) -> &'static str {
+  match cell {
+    Data::Int(_) => "Int",
+    Data::Float(_) => "Float",
+    Data::String(_) => "String",
+    Data::Bool(_) => "Bool",
+    Data::DateTime(value) if value.is_duration() => "DateTime (duration)",
+    Data::DateTime(_) => "DateTime",
+    Data::DateTimeIso(_) => "DateTimeIso",
+    Data::DurationIso(_) => "DurationIso",
+    Data::Error(_) => "Error",
+    Data::Empty => "Empty",
+  }
+}
+
+fn cell_text(cell: &Data) -> String {
+  match cell {
+    Data::DateTime(value) if value.is_duration() => {
+      let seconds = value.as_duration().map_or(0, |duration| duration.num_seconds());
+      format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    }
+    Data::DateTime(value) => match value.as_datetime() {
+      Some(datetime) if datetime.time() == NaiveTime::MIN => {
+        datetime.format("%Y-%m-%d").to_string()
+      }
+      Some(datetime) => datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
+      None => value.to_string(),
+    },
+    cell => cell.to_string(),
+  }
+}
+
+fn range_rows(
+  range: &Range<Data>,
+  limit: usize,
+  progress: &mut dyn FnMut(usize, usize),
+) -> Vec<Vec<String>> {
+  let total = range.height().min(limit);
+  let mut rows = Vec::with_capacity(total);
+  for (i, row) in range.rows().take(limit).enumerate() {
+    if i % PROGRESS_CHUNK == 0 {
+      progress(i, total);
+    }
+    rows.push(row.iter().map(cell_text).collect());
+  }
+  rows
+}
+
+// Streams cells so a huge sheet is never held as both a Range and strings;
+// like worksheet_range, rows span the bounding box of non-empty cells.
+fn cell_rows<'a>(
+  next: &mut dyn FnMut() -> Option<Option<Cell<DataRef<'a>>>>,
+  total: usize,
+  limit: usize,
+  progress: &mut dyn FnMut(usize, usize),
+) -> Option<Vec<Vec<String>>> {
+  let total = total.min(limit);
+  let mut rows: Vec<Vec<String>> = Vec::new();
+  let mut first_row = None;
+  let (mut first_col, mut last_col) = (usize::MAX, 0);
+  while let Some(cell) = next()? {
+    if let DataRef::Empty = cell.get_value() {
+      continue;
+    }
+    let (row, col) = cell.get_position();
+    let row = row.checked_sub(*first_row.get_or_insert(row))? as usize;
+    // Cells arrive row by row, so nothing past the limit is read; empty rows before this
+    // cell still count towards it
+    if row >= limit {
+      rows.resize(limit, Vec::new());
+      break;
+    }
+    let col = col as usize;
+    while rows.len() <= row {
+      if rows.len().is_multiple_of(PROGRESS_CHUNK) {
+        progress(rows.len(), total.max(rows.len()));
+      }
+      rows.push(Vec::new());
+    }
+    let cells = &mut rows[row];
+    if cells.len() <= col {
+      cells.resize(col + 1, String::new());
+    }
+    cells[col] = cell_text(&Data::from(cell.get_value().clone()));
+    first_col = first_col.min(col);
+    last_col = last_col.max(col);
+  }
+  for cells in &mut rows {
+    cells.resize(last_col + 1, String::new());
+    cells.drain(..first_col);
+  }
+  Some(rows)
+}
+
+fn named_ranges(workbook: &mut Sheets<BufReader<File>>) -> Vec<NamedRange> {
+  let sheets = workbook.sheet_names();
+  let mut ranges: Vec<NamedRange> = workbook
+    .defined_names()
+    .iter()
+    .filter(|(name, _)| !name.starts_with("_xlnm"))
+    .filter_map(|(name, formula)| {
+      let (sheet, area) = formula.rsplit_once('!')?;
+      let sheet = sheet.trim_start_matches('=').trim_matches('\'').replace("''", "'");
+      let (start, end) = area.split_once(':').unwrap_or((area, area));
+      let area = (cell_position(start)?, cell_position(end)?);
+      Some(NamedRange { name: name.clone(), sheet, area: Some(area) })
+    })
+    .collect();
+  if let Sheets::Xlsx(xlsx) = workbook {
+    if xlsx.load_tables().is_ok() {
+      for sheet in &sheets {
+        for name in xlsx.table_names_in_sheet(sheet) {
+          ranges.push(NamedRange { name: name.clone(), sheet: sheet.clone(), area: None });
+        }
+      }
+    }
+  }
+  ranges.retain(|range| sheets.contains(&range.sheet) && !sheets.contains(&range.name));
+  ranges
+}
+
+fn cell_position(cell: &str) -> Option<(u32, u32)> {
+  let cell = cell.replace('$', "");
+  let split = cell.find(|c: char| c.is_ascii_digit())?;
+  let (letters, digits) = cell.split_at(split);
+  if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_alphabetic()) {
+    return None;
+  }
+  let col = letters.chars().fold(0, |acc, c| acc * 26 + (c.to_ascii_uppercase() as u32 - 64));
+  let row: u32 = digits.parse().ok()?;
+  Some((row.checked_sub(1)?, col - 1))
+}
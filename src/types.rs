@@ -1,45 +1,485 @@
-use calamine::{Reader, Xlsx};
+use chrono::Local;
 use ratatui::style::{Color, Modifier, Style};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::{
-  io::{Read, Seek},
+  borrow::Cow,
+  cmp::Ordering,
+  collections::{BTreeSet, HashMap, HashSet},
+  env,
   path::Path,
   time::Instant,
 };
 use tui_input::Input;
 
-use crate::utils::normalize_text;
+use crate::{
+  config::Config,
+  source::Source,
+  utils::{
+    compare_cells, group_ordered, header_cells, infer_type, normalize_text, parse_date,
+    parse_number, parse_row_range, valid_date_format,
+  },
+};
 pub const FOCUSED_STYLE: Style = Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD);
+pub const MATCH_STYLE: Style = Style::new().fg(Color::Black).bg(Color::Yellow);
+const TYPE_SAMPLE_ROWS: usize = 1000;
+const HISTORY_DEPTH: usize = 100;
+pub const MIN_ROWS_PER_PAGE: usize = 5;
+const PROGRESS_CHUNK: usize = 1000;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ColumnState {
   Hidden,
   Original,
   NonEmpty,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ColumnConfig {
+  #[serde(with = "input_text")]
   pub prefix: Input,
+  #[serde(with = "input_text")]
   pub postfix: Input,
+  pub required: bool,
+  pub dedup_key: bool,
+  pub number_format: NumberFormat,
+  #[serde(with = "input_text")]
+  pub min: Input,
+  #[serde(with = "input_text")]
+  pub max: Input,
+  #[serde(with = "input_text")]
+  pub pattern: Input,
+  pub bool_map: bool,
+  #[serde(with = "input_text")]
+  pub truthy: Input,
+  #[serde(with = "input_text")]
+  pub falsy: Input,
+  pub affix_typed: bool,
+  pub affix_when: AffixWhen,
+  #[serde(with = "input_text")]
+  pub affix_test: Input,
+  #[serde(with = "input_text")]
+  pub trim_chars: Input,
+  pub trim_all: bool,
+  pub trim: Option<bool>,
+  #[serde(with = "input_text")]
+  pub nulls: Input,
+  pub value_type: Option<ColumnType>,
+  #[serde(with = "input_text")]
+  pub date_format: Input,
+  pub exclude: bool,
+}
+
+mod input_text {
+  use serde::{Deserialize, Deserializer, Serializer};
+  use tui_input::Input;
+
+  pub fn serialize<S: Serializer>(input: &Input, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(input.value())
+  }
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Input, D::Error> {
+    String::deserialize(deserializer).map(|value| Input::default().with_value(value))
+  }
+  pub fn serialize_all<S: Serializer>(inputs: &[Input], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(inputs.iter().map(Input::value))
+  }
+}
+
+impl ColumnConfig {
+  // typed_values is the global Typed toggle, which makes inferred numbers typed in every column
+  pub fn applies(&self, edit: ExportEdit, typed_values: bool) -> bool {
+    match edit {
+      ExportEdit::Truthy | ExportEdit::Falsy => self.bool_map,
+      ExportEdit::AffixTyped => {
+        self.bool_map
+          || self.number_format != NumberFormat::AsIs
+          || self.value_type.map_or(typed_values, |value_type| value_type != ColumnType::String)
+      }
+      ExportEdit::AffixTest => {
+        matches!(self.affix_when, AffixWhen::NotStartingWith | AffixWhen::Matching)
+      }
+      ExportEdit::TrimAll => !self.trim_chars.value().is_empty(),
+      _ => true,
+    }
+  }
+  pub fn affix_pattern(&self) -> Result<Option<Regex>, regex::Error> {
+    match self.affix_when {
+      AffixWhen::Matching => Regex::new(self.affix_test.value()).map(Some),
+      _ => Ok(None),
+    }
+  }
+  pub fn decorates(&self, value: &str, pattern: Option<&Regex>) -> bool {
+    match self.affix_when {
+      AffixWhen::Always => true,
+      AffixWhen::NonEmpty => !value.trim().is_empty(),
+      AffixWhen::NotStartingWith => {
+        let test = match self.affix_test.value() {
+          "" => self.prefix.value(),
+          test => test,
+        };
+        test.is_empty() || !value.starts_with(test)
+      }
+      AffixWhen::Matching => pattern.is_some_and(|re| re.is_match(value)),
+    }
+  }
+  pub fn strip<'a>(&self, value: &'a str) -> Cow<'a, str> {
+    let chars: Vec<char> = self.trim_chars.value().chars().collect();
+    if chars.is_empty() {
+      Cow::Borrowed(value)
+    } else if self.trim_all {
+      Cow::Owned(value.chars().filter(|c| !chars.contains(c)).collect())
+    } else {
+      Cow::Borrowed(value.trim_matches(chars.as_slice()))
+    }
+  }
+  pub fn format_date(&self, value: &str) -> Option<String> {
+    let format = self.date_format.value();
+    if format.is_empty() || !valid_date_format(format) {
+      return None;
+    }
+    parse_date(value).map(|datetime| datetime.format(format).to_string())
+  }
+  pub fn map_bool(&self, value: &str) -> Option<serde_json::Value> {
+    if !self.bool_map {
+      return None;
+    }
+    let value = value.trim().to_lowercase();
+    if value.is_empty() {
+      return Some(serde_json::Value::Null);
+    }
+    let matches = |input: &Input, default: &str| {
+      let tokens = if input.value().trim().is_empty() { default } else { input.value() };
+      tokens.split(',').any(|token| token.trim().to_lowercase() == value)
+    };
+    if matches(&self.truthy, "true,yes,1") {
+      Some(true.into())
+    } else if matches(&self.falsy, "false,no,0") {
+      Some(false.into())
+    } else {
+      None
+    }
+  }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AffixWhen {
+  #[default]
+  Always,
+  NonEmpty,
+  NotStartingWith,
+  Matching,
+}
+
+impl AffixWhen {
+  pub fn label(&self) -> &'static str {
+    match self {
+      AffixWhen::Always => "always",
+      AffixWhen::NonEmpty => "non-empty",
+      AffixWhen::NotStartingWith => "not starting with",
+      AffixWhen::Matching => "matching",
+    }
+  }
+  pub fn next(&self) -> Self {
+    match self {
+      AffixWhen::Always => AffixWhen::NonEmpty,
+      AffixWhen::NonEmpty => AffixWhen::NotStartingWith,
+      AffixWhen::NotStartingWith => AffixWhen::Matching,
+      AffixWhen::Matching => AffixWhen::Always,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NumberFormat {
+  #[default]
+  AsIs,
+  Integer,
+  Decimals(u8),
+}
+
+impl NumberFormat {
+  pub fn label(&self) -> String {
+    match self {
+      NumberFormat::AsIs => "as-is".to_string(),
+      NumberFormat::Integer => "integer".to_string(),
+      NumberFormat::Decimals(places) => format!("{} dp", places),
+    }
+  }
+  pub fn next(&self) -> Self {
+    match self {
+      NumberFormat::AsIs => NumberFormat::Integer,
+      NumberFormat::Integer => NumberFormat::Decimals(1),
+      NumberFormat::Decimals(places) if *places < 4 => NumberFormat::Decimals(places + 1),
+      NumberFormat::Decimals(_) => NumberFormat::AsIs,
+    }
+  }
+  pub fn apply(&self, value: &str) -> Option<serde_json::Value> {
+    let number = parse_number(value)?;
+    match self {
+      NumberFormat::AsIs => None,
+      NumberFormat::Integer => Some((number.round() as i64).into()),
+      NumberFormat::Decimals(places) => {
+        let scale = 10f64.powi(*places as i32);
+        serde_json::Number::from_f64((number * scale).round() / scale).map(Into::into)
+      }
+    }
+  }
+}
+
+pub enum ColumnFilter {
+  Contains(String),
+  Equals(String),
+  NotEquals(String),
+  Matches(Regex),
+  Compare(Ordering, bool, f64),
+}
+
+impl ColumnFilter {
+  pub const SYNTAX: &'static str = "text, =text, !=text, ~regex, <n, <=n, >n or >=n";
+  pub fn parse(text: &str) -> Result<Option<Self>, String> {
+    let text = text.trim();
+    let number =
+      |rest: &str| parse_number(rest).ok_or(format!("'{}' is not a number", rest.trim()));
+    let filter = if text.is_empty() {
+      return Ok(None);
+    } else if let Some(rest) = text.strip_prefix(">=") {
+      ColumnFilter::Compare(Ordering::Greater, true, number(rest)?)
+    } else if let Some(rest) = text.strip_prefix("<=") {
+      ColumnFilter::Compare(Ordering::Less, true, number(rest)?)
+    } else if let Some(rest) = text.strip_prefix('>') {
+      ColumnFilter::Compare(Ordering::Greater, false, number(rest)?)
+    } else if let Some(rest) = text.strip_prefix('<') {
+      ColumnFilter::Compare(Ordering::Less, false, number(rest)?)
+    } else if let Some(rest) = text.strip_prefix("!=") {
+      ColumnFilter::NotEquals(rest.trim().to_lowercase())
+    } else if let Some(rest) = text.strip_prefix('=') {
+      ColumnFilter::Equals(rest.trim().to_lowercase())
+    } else if let Some(rest) = text.strip_prefix('~') {
+      ColumnFilter::Matches(Regex::new(rest).map_err(|err| {
+        err.to_string().lines().last().unwrap_or_default().trim_start_matches("error: ").to_string()
+      })?)
+    } else {
+      ColumnFilter::Contains(text.to_lowercase())
+    };
+    Ok(Some(filter))
+  }
+  pub fn matches(&self, value: &str) -> bool {
+    match self {
+      ColumnFilter::Contains(needle) => value.to_lowercase().contains(needle),
+      ColumnFilter::Equals(expected) => value.trim().to_lowercase() == *expected,
+      ColumnFilter::NotEquals(expected) => value.trim().to_lowercase() != *expected,
+      ColumnFilter::Matches(pattern) => pattern.is_match(value),
+      ColumnFilter::Compare(ordering, inclusive, bound) => parse_number(value)
+        .and_then(|number| number.partial_cmp(bound))
+        .is_some_and(|cmp| cmp == *ordering || (*inclusive && cmp == Ordering::Equal)),
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct MergeSheet {
+  pub name: String,
+  pub header_row: usize,
+  pub header: Vec<String>,
+  pub pairs: HashMap<String, String>,
+  pub include: bool,
+}
+
+impl MergeSheet {
+  // The header cell that fills a merged column from this sheet
+  pub fn source(&self, column: &str, primary: &[String]) -> Option<&String> {
+    if primary.iter().any(|c| c == column) {
+      return self.pairs.get(column);
+    }
+    self.header.iter().find(|cell| *cell == column && !self.pairs.values().any(|p| p == column))
+  }
+}
+
+#[derive(Clone, Serialize)]
+pub struct Snapshot {
+  pub columns: Vec<ColumnState>,
+  pub column_configs: Vec<ColumnConfig>,
+  #[serde(serialize_with = "input_text::serialize_all")]
+  pub custom_keys: Vec<Input>,
+  #[serde(serialize_with = "input_text::serialize_all")]
+  pub column_filters: Vec<Input>,
+  #[serde(with = "input_text")]
+  pub row_filter: Input,
+  pub sort: Option<(usize, bool)>,
+  #[serde(serialize_with = "sorted")]
+  pub marked_rows: HashSet<usize>,
+  pub keep_marked: bool,
 }
 
-impl Default for ColumnConfig {
-  fn default() -> Self {
-    Self { prefix: Input::default(), postfix: Input::default() }
+fn sorted<S: serde::Serializer>(rows: &HashSet<usize>, serializer: S) -> Result<S::Ok, S::Error> {
+  serializer.collect_seq(rows.iter().collect::<BTreeSet<_>>())
+}
+
+// Input has no PartialEq, so snapshots compare by their serialized form, which covers every
+// field of Snapshot and ColumnConfig as they grow
+impl PartialEq for Snapshot {
+  fn eq(&self, other: &Self) -> bool {
+    serde_json::to_value(self).ok() == serde_json::to_value(other).ok()
+  }
+}
+
+// Everything a merge, join or summary replaces, so that it can be undone
+pub struct Workspace {
+  pub label: &'static str,
+  pub step: Step,
+  pub data: Vec<Vec<String>>,
+  pub sheets: Vec<String>,
+  pub selected_sheet: Option<usize>,
+  pub first_row: usize,
+  pub header_backup: Option<(usize, Vec<String>)>,
+  pub trimmed_rows: Vec<Vec<String>>,
+  pub merged_from: Vec<(String, usize)>,
+  pub merge_info: Option<Vec<MergeSheet>>,
+  pub export_filename: Input,
+  pub row_filter_column: Option<usize>,
+  pub snapshot: Snapshot,
+  pub undo_stack: Vec<Snapshot>,
+  pub redo_stack: Vec<Snapshot>,
+}
+
+pub struct NumberStats {
+  pub count: usize,
+  pub sum: f64,
+  pub min: f64,
+  pub max: f64,
+}
+
+impl NumberStats {
+  pub fn of(values: impl IntoIterator<Item = f64>) -> Option<Self> {
+    let mut stats: Option<Self> = None;
+    for value in values {
+      let stats = stats.get_or_insert(NumberStats { count: 0, sum: 0.0, min: value, max: value });
+      stats.count += 1;
+      stats.sum += value;
+      stats.min = stats.min.min(value);
+      stats.max = stats.max.max(value);
+    }
+    stats
+  }
+  pub fn mean(&self) -> f64 {
+    self.sum / self.count as f64
+  }
+}
+
+pub struct JoinState {
+  pub sheets: Vec<String>,
+  pub sheet: usize,
+  pub rows: Vec<Vec<String>>,
+  pub header_row: usize,
+  pub key: usize,
+  pub other_key: usize,
+  pub field: usize,
+}
+
+impl JoinState {
+  pub fn header(&self) -> Vec<String> {
+    self.rows.get(self.header_row).cloned().unwrap_or_default()
+  }
+  pub fn match_key(&mut self, column: &str) {
+    let column = normalize_text(column);
+    self.other_key =
+      self.header().iter().position(|cell| normalize_text(cell) == column).unwrap_or(0);
+  }
+  // First row of the other sheet for every key, compared trimmed and case-insensitively
+  pub fn lookup(&self) -> HashMap<String, &Vec<String>> {
+    let mut lookup = HashMap::new();
+    for row in self.rows.iter().skip(self.header_row + 1) {
+      let key = row.get(self.other_key).map_or(String::new(), |cell| cell.trim().to_lowercase());
+      if !key.is_empty() {
+        lookup.entry(key).or_insert(row);
+      }
+    }
+    lookup
+  }
+  pub fn matches(&self, rows: &[Vec<String>]) -> usize {
+    let lookup = self.lookup();
+    rows
+      .iter()
+      .filter(|row| {
+        row.get(self.key).is_some_and(|cell| lookup.contains_key(&cell.trim().to_lowercase()))
+      })
+      .count()
+  }
+  // The other sheet's columns to append, renamed when they clash with an existing one
+  pub fn added_columns(&self, existing: &[String]) -> Vec<(usize, String)> {
+    let sheet = &self.sheets[self.sheet];
+    self
+      .header()
+      .iter()
+      .enumerate()
+      .filter(|(idx, cell)| *idx != self.other_key && !cell.trim().is_empty())
+      .map(|(idx, cell)| {
+        let name = cell.trim().to_string();
+        if existing.iter().any(|column| column.trim() == name) {
+          (idx, format!("{} ({})", name, sheet))
+        } else {
+          (idx, name)
+        }
+      })
+      .collect()
   }
 }
 
 pub struct App {
   pub sheets: Vec<String>,
+  pub range_hints: HashMap<String, String>,
   pub selected_sheet: Option<usize>,
   pub data: Vec<Vec<String>>,
   pub first_row: usize,
+  pub header_rows: usize,
+  pub header_separator: Input,
+  pub row_trim_edit: RowTrimEdit,
+  pub last_row_input: Input,
+  pub stop_at_empty: bool,
+  pub header_backup: Option<(usize, Vec<String>)>,
+  pub trimmed_rows: Vec<Vec<String>>,
   pub columns: Vec<ColumnState>,
+  pub empty_columns: Vec<bool>,
   pub selected_column: usize,
+  pub column_offset: usize,
+  pub list_scroll: usize,
+  pub selected_row: usize,
+  pub popup: Option<Popup>,
+  pub undo_stack: Vec<Snapshot>,
+  pub redo_stack: Vec<Snapshot>,
+  pub popup_text: String,
+  pub popup_changed: HashSet<usize>,
+  pub popup_scroll: u16,
+  pub cell_type: Option<&'static str>,
+  pub pending_load: bool,
+  pub pending_export: bool,
+  pub workspace_undo: Vec<Workspace>,
+  pub workspace_redo: Vec<Workspace>,
+  pub join: Option<JoinState>,
+  pub clipboard: Option<arboard::Clipboard>,
+  pub row_filter: Input,
+  pub table_layout: TableLayout,
+  pub infer_types: bool,
+  pub any_non_empty: bool,
+  pub row_filter_column: Option<usize>,
+  pub editing_filter: bool,
+  pub column_filters: Vec<Input>,
+  pub sort: Option<(usize, bool)>,
+  pub marked_rows: HashSet<usize>,
+  pub keep_marked: bool,
+  pub editing_column_filter: bool,
+  pub data_search: Input,
+  pub editing_search: bool,
   pub step: Step,
   pub row_input: Input,
   pub current_page: usize,
   pub rows_per_page: usize,
+  // Rows read from a sheet at most, so a huge sheet can be previewed without loading it all
+  pub max_rows: Option<usize>,
   pub sheet_search: Input,
   pub matching_sheets: Vec<usize>,
   pub column_configs: Vec<ColumnConfig>,
@@ -47,27 +487,119 @@ pub struct App {
   pub export_edit: ExportEdit,
   pub custom_keys: Vec<Input>,
   pub export_filename: Input,
+  // Set once the Filename field is typed in, so it then wins over the template
+  pub filename_edited: bool,
+  // The extension of an explicit --out path, which is then written exactly as given
+  pub out_extension: Option<String>,
+  pub filename_template: Input,
   pub export_toast: Option<String>,
   pub export_toast_time: Option<Instant>,
-  pub merge_info: Option<Vec<(String, Vec<String>)>>,
+  pub merge_info: Option<Vec<MergeSheet>>,
+  pub merge_selected: usize,
+  pub merge_dropped: HashSet<String>,
+  pub merge_focus: usize,
+  pub merge_union: bool,
+  pub merge_fuzzy: bool,
+  pub merge_provenance: bool,
+  pub merged_from: Vec<(String, usize)>,
   pub deduplicate: bool,
+  pub append: bool,
+  pub write_schema: bool,
+  pub write_manifest: bool,
+  pub normalize_dedup: bool,
+  pub matrix: bool,
+  pub verbatim_keys: bool,
+  pub trim_values: bool,
+  pub typed_values: bool,
+  pub null_tokens: Input,
+  pub bom: bool,
+  pub crlf: bool,
+  pub indent: Indent,
+  pub sql_dialect: SqlDialect,
+  pub compact: bool,
+  pub export_format: ExportFormat,
+  pub table_name: Input,
+  pub out_dir: Input,
+  pub xml_root: Input,
+  pub xml_record: Input,
+  pub partition_column: Option<usize>,
+  pub drop_partition: bool,
+  pub rows_from: Input,
+  pub rows_to: Input,
+  pub group_column: Option<usize>,
+  pub items_key: Input,
   pub original_filename: String,
+  pub use_config: bool,
+  pub toast_seconds: u64,
 }
 
 impl App {
-  pub fn new<T: Read + Seek>(xlsx: &mut Xlsx<T>, original_filename: &str) -> Self {
-    let sheets = xlsx.sheet_names().to_owned();
+  pub fn new(source: &Source, original_filename: &str, use_config: bool) -> Self {
+    let sheets = source.sheet_names();
+    let config = if use_config { Config::load() } else { Config::default() };
+    let rows_per_page = env::var("XLRS_ROWS_PER_PAGE")
+      .ok()
+      .and_then(|rows| rows.trim().parse::<usize>().ok())
+      .map_or(10, |rows| rows.max(MIN_ROWS_PER_PAGE));
+    let max_rows = env::var("XLRS_MAX_ROWS").ok().and_then(|rows| rows.trim().parse().ok());
+    let start_sheet = env::var("XLRS_SHEET")
+      .ok()
+      .and_then(|name| sheets.iter().position(|sheet| *sheet == name))
+      .filter(|_| !source.is_csv());
     Self {
       sheets,
-      selected_sheet: Some(0),
+      range_hints: source
+        .named_ranges()
+        .iter()
+        .map(|range| (range.name.clone(), range.hint()))
+        .collect(),
+      selected_sheet: start_sheet.or(Some(0)),
       data: Vec::new(),
       first_row: 0,
+      header_rows: 1,
+      header_separator: Input::default().with_value(" ".to_string()),
+      row_trim_edit: RowTrimEdit::FirstRow,
+      last_row_input: Input::default(),
+      stop_at_empty: false,
+      header_backup: None,
+      trimmed_rows: Vec::new(),
       columns: Vec::new(),
+      empty_columns: Vec::new(),
       selected_column: 0,
+      column_offset: 0,
+      list_scroll: 0,
+      selected_row: 0,
+      popup: None,
+      undo_stack: Vec::new(),
+      redo_stack: Vec::new(),
+      popup_text: String::new(),
+      popup_changed: HashSet::new(),
+      popup_scroll: 0,
+      cell_type: None,
+      pending_load: start_sheet.is_some(),
+      pending_export: false,
+      workspace_undo: Vec::new(),
+      workspace_redo: Vec::new(),
+      join: None,
+      clipboard: None,
+      row_filter: Input::default(),
+      table_layout: TableLayout::Fit,
+      infer_types: false,
+      any_non_empty: false,
+      row_filter_column: None,
+      editing_filter: false,
+      column_filters: Vec::new(),
+      sort: None,
+      marked_rows: HashSet::new(),
+      keep_marked: false,
+      editing_column_filter: false,
+      data_search: Input::default(),
+      editing_search: false,
       step: Step::SheetSelect,
       row_input: Input::default(),
       current_page: 0,
-      rows_per_page: 10,
+      rows_per_page,
+      max_rows,
       sheet_search: Input::default(),
       matching_sheets: Vec::new(),
       column_configs: Vec::new(),
@@ -75,22 +607,268 @@ impl App {
       export_edit: ExportEdit::FileName,
       custom_keys: Vec::new(),
       export_filename: Input::default().with_value("export".to_string()),
+      filename_edited: false,
+      out_extension: None,
+      filename_template: Input::default().with_value(config.filename_template.clone()),
       export_toast: None,
       export_toast_time: None,
       merge_info: None,
-      deduplicate: true,
+      merge_selected: 0,
+      merge_dropped: HashSet::new(),
+      merge_focus: 0,
+      merge_union: false,
+      merge_fuzzy: false,
+      merge_provenance: false,
+      merged_from: Vec::new(),
+      deduplicate: config.deduplicate,
+      append: false,
+      write_schema: false,
+      write_manifest: false,
+      normalize_dedup: false,
+      matrix: false,
+      verbatim_keys: config.verbatim_keys,
+      trim_values: config.trim_values,
+      typed_values: config.typed_values,
+      null_tokens: Input::default().with_value(config.null_tokens.clone()),
+      bom: config.bom,
+      crlf: config.crlf,
+      indent: config.indent,
+      sql_dialect: config.sql_dialect,
+      compact: config.compact,
+      export_format: config.format,
+      table_name: Input::default(),
+      out_dir: Input::default().with_value(config.out_dir.clone()),
+      xml_root: Input::new("records".to_string()),
+      xml_record: Input::new("record".to_string()),
+      partition_column: None,
+      drop_partition: false,
+      rows_from: Input::default(),
+      rows_to: Input::default(),
+      group_column: None,
+      items_key: Input::default().with_value("items".to_string()),
       original_filename: original_filename.to_string(),
+      use_config,
+      toast_seconds: config.toast_seconds,
     }
   }
   pub fn handle_back(&self) -> Step {
     match self.step {
       Step::SheetSelect => Step::SheetSelect,
       Step::Export => Step::Preview,
-      Step::Preview => Step::ColSelect,
+      Step::Preview | Step::Join => Step::ColSelect,
       Step::ColSelect | Step::MergePrompt => Step::RowTrim,
       Step::RowTrim => Step::SheetSelect,
     }
   }
+  // The first header row and the header row count typed on the row trim step
+  pub fn header_range(&self) -> Option<(usize, usize)> {
+    let (first, last) = parse_row_range(self.row_input.value())?;
+    Some((first, if last > first { last - first + 1 } else { self.header_rows }))
+  }
+  // One past the last data row kept below the header ending at header_row
+  pub fn data_end(&self, header_row: usize) -> Result<usize, String> {
+    let mut end = self.data.len();
+    let last_row = self.last_row_input.value().trim();
+    if !last_row.is_empty() {
+      let last_row: usize =
+        last_row.parse().map_err(|_| format!("Last row '{}' is not a number", last_row))?;
+      if last_row <= header_row {
+        return Err(format!("Last row {} is not below the header", last_row));
+      }
+      end = end.min(last_row + 1);
+    }
+    if self.stop_at_empty {
+      let start = (header_row + 1).min(end);
+      if let Some(pos) =
+        self.data[start..end].iter().position(|row| row.iter().all(|cell| cell.trim().is_empty()))
+      {
+        end = start + pos;
+      }
+    }
+    Ok(end)
+  }
+  pub fn snapshot(&self) -> Snapshot {
+    Snapshot {
+      columns: self.columns.clone(),
+      column_configs: self.column_configs.clone(),
+      custom_keys: self.custom_keys.clone(),
+      column_filters: self.column_filters.clone(),
+      row_filter: self.row_filter.clone(),
+      sort: self.sort,
+      marked_rows: self.marked_rows.clone(),
+      keep_marked: self.keep_marked,
+    }
+  }
+  pub fn restore(&mut self, snapshot: Snapshot) {
+    self.columns = snapshot.columns;
+    self.column_configs = snapshot.column_configs;
+    self.custom_keys = snapshot.custom_keys;
+    self.column_filters = snapshot.column_filters;
+    self.row_filter = snapshot.row_filter;
+    self.sort = snapshot.sort;
+    self.marked_rows = snapshot.marked_rows;
+    self.keep_marked = snapshot.keep_marked;
+    self.current_page = self.current_page.min(self.total_pages().saturating_sub(1));
+  }
+  pub fn workspace(&self, label: &'static str) -> Workspace {
+    Workspace {
+      label,
+      step: self.step,
+      data: self.data.clone(),
+      sheets: self.sheets.clone(),
+      selected_sheet: self.selected_sheet,
+      first_row: self.first_row,
+      header_backup: self.header_backup.clone(),
+      trimmed_rows: self.trimmed_rows.clone(),
+      merged_from: self.merged_from.clone(),
+      merge_info: self.merge_info.clone(),
+      export_filename: self.export_filename.clone(),
+      row_filter_column: self.row_filter_column,
+      snapshot: self.snapshot(),
+      undo_stack: self.undo_stack.clone(),
+      redo_stack: self.redo_stack.clone(),
+    }
+  }
+  pub fn swap_workspace(&mut self, workspace: Workspace) -> Workspace {
+    let current = Workspace {
+      label: workspace.label,
+      step: workspace.step,
+      data: std::mem::replace(&mut self.data, workspace.data),
+      sheets: std::mem::replace(&mut self.sheets, workspace.sheets),
+      selected_sheet: std::mem::replace(&mut self.selected_sheet, workspace.selected_sheet),
+      first_row: std::mem::replace(&mut self.first_row, workspace.first_row),
+      header_backup: std::mem::replace(&mut self.header_backup, workspace.header_backup),
+      trimmed_rows: std::mem::replace(&mut self.trimmed_rows, workspace.trimmed_rows),
+      merged_from: std::mem::replace(&mut self.merged_from, workspace.merged_from),
+      merge_info: std::mem::replace(&mut self.merge_info, workspace.merge_info),
+      export_filename: std::mem::replace(&mut self.export_filename, workspace.export_filename),
+      row_filter_column: std::mem::replace(
+        &mut self.row_filter_column,
+        workspace.row_filter_column,
+      ),
+      snapshot: self.snapshot(),
+      undo_stack: std::mem::replace(&mut self.undo_stack, workspace.undo_stack),
+      redo_stack: std::mem::replace(&mut self.redo_stack, workspace.redo_stack),
+    };
+    self.restore(workspace.snapshot);
+    current
+  }
+  pub fn record_history(&mut self, before: Snapshot) {
+    if before != self.snapshot() {
+      self.undo_stack.push(before);
+      if self.undo_stack.len() > HISTORY_DEPTH {
+        self.undo_stack.remove(0);
+      }
+      self.redo_stack.clear();
+      self.workspace_redo.clear();
+    }
+  }
+  pub fn undo(&mut self) {
+    if let Some(snapshot) = self.undo_stack.pop() {
+      self.redo_stack.push(self.snapshot());
+      self.restore(snapshot);
+    }
+  }
+  pub fn redo(&mut self) {
+    if let Some(snapshot) = self.redo_stack.pop() {
+      self.undo_stack.push(self.snapshot());
+      self.restore(snapshot);
+    }
+  }
+  pub fn merge_sheets(&self) -> impl Iterator<Item = &MergeSheet> {
+    self.merge_info.iter().flatten().filter(|sheet| sheet.include && !sheet.pairs.is_empty())
+  }
+  pub fn primary_columns(&self) -> Vec<String> {
+    self.data.get(self.first_row).map_or(Vec::new(), |row| header_cells(row))
+  }
+  pub fn merge_columns(&self) -> Vec<String> {
+    let primary = self.primary_columns();
+    if !self.merge_union {
+      return primary
+        .into_iter()
+        .filter(|s| self.merge_sheets().all(|sheet| sheet.pairs.contains_key(s)))
+        .collect();
+    }
+    let mut columns = primary.clone();
+    for sheet in self.merge_sheets() {
+      for cell in &sheet.header {
+        if !columns.contains(cell) && sheet.source(cell, &primary).is_some() {
+          columns.push(cell.clone());
+        }
+      }
+    }
+    columns
+  }
+  // The columns listed on the merge prompt
+  pub fn merge_rows(&self) -> Vec<String> {
+    if self.merge_union {
+      self.merge_columns()
+    } else {
+      self.primary_columns()
+    }
+  }
+  // One row per distinct value of the column, with the count and the sum, min, max and
+  // average of every visible numeric column
+  pub fn summary(&self, group_col: usize) -> Vec<Vec<String>> {
+    let header = &self.data[self.first_row];
+    let name = |col_idx: usize| header.get(col_idx).map_or("", |s| s.trim()).to_string();
+    let numeric: Vec<usize> = self
+      .visible_columns()
+      .into_iter()
+      .zip(self.column_types())
+      .filter(|(col_idx, column_type)| {
+        *col_idx != group_col && matches!(column_type, ColumnType::Int | ColumnType::Float)
+      })
+      .map(|(col_idx, _)| col_idx)
+      .collect();
+    let mut summary = vec![std::iter::once(name(group_col))
+      .chain(std::iter::once("count".to_string()))
+      .chain(numeric.iter().flat_map(|&col_idx| {
+        ["sum", "min", "max", "avg"].map(|stat| format!("{} {}", name(col_idx), stat))
+      }))
+      .collect()];
+    let cell = |row_idx: usize, col_idx: usize| self.data[row_idx].get(col_idx).map_or("", |s| s);
+    for (value, rows) in
+      group_ordered(self.visible_rows(), |&row_idx| cell(row_idx, group_col).trim().to_string())
+    {
+      let mut row = vec![value, rows.len().to_string()];
+      for &col_idx in &numeric {
+        let values = rows
+          .iter()
+          .filter_map(|&row_idx| self.normalize(col_idx, cell(row_idx, col_idx)))
+          .filter_map(|value| parse_number(&value));
+        match NumberStats::of(values) {
+          Some(stats) => row.extend(
+            [stats.sum, stats.min, stats.max, stats.mean()].map(|number| number.to_string()),
+          ),
+          None => row.extend(std::iter::repeat_n(String::new(), 4)),
+        }
+      }
+      summary.push(row);
+    }
+    summary
+  }
+  pub fn save_config(&self) {
+    if self.use_config {
+      let config = Config {
+        deduplicate: self.deduplicate,
+        format: self.export_format,
+        toast_seconds: self.toast_seconds,
+        filename_template: self.filename_template.value().to_string(),
+        out_dir: self.out_dir.value().to_string(),
+        trim_values: self.trim_values,
+        typed_values: self.typed_values,
+        null_tokens: self.null_tokens.value().to_string(),
+        bom: self.bom,
+        crlf: self.crlf,
+        indent: self.indent,
+        sql_dialect: self.sql_dialect,
+        compact: self.compact,
+        verbatim_keys: self.verbatim_keys,
+      };
+      let _ = config.save();
+    }
+  }
   pub fn visible_columns(&self) -> Vec<usize> {
     self
       .columns
@@ -100,18 +878,108 @@ impl App {
       .map(|(i, _)| i)
       .collect()
   }
+  pub fn visible_rows(&self) -> Vec<usize> {
+    let filters = self.active_column_filters();
+    let mut rows: Vec<usize> = (self.first_row + 1..self.data.len())
+      .filter(|&i| self.is_row_visible(&self.data[i], &filters))
+      .collect();
+    if let Some((col_idx, descending)) = self.sort {
+      let cell = |row_idx: usize| self.data[row_idx].get(col_idx).map_or("", |s| s.trim());
+      rows.sort_by(|&a, &b| {
+        let ordering = compare_cells(cell(a), cell(b));
+        if descending {
+          ordering.reverse()
+        } else {
+          ordering
+        }
+      });
+    }
+    rows
+  }
+  pub fn active_column_filters(&self) -> Vec<(usize, ColumnFilter)> {
+    self
+      .column_filters
+      .iter()
+      .enumerate()
+      .filter_map(|(col_idx, input)| Some((col_idx, ColumnFilter::parse(input.value()).ok()??)))
+      .collect()
+  }
+  pub fn column_types(&self) -> Vec<ColumnType> {
+    let rows: Vec<usize> = self.visible_rows().into_iter().take(TYPE_SAMPLE_ROWS).collect();
+    self
+      .visible_columns()
+      .into_iter()
+      .map(|col_idx| {
+        let values: Vec<Cow<str>> = rows
+          .iter()
+          .filter_map(|&r| self.data[r].get(col_idx))
+          .filter_map(|s| self.normalize(col_idx, s))
+          .collect();
+        infer_type(values.iter().map(|s| s.as_ref()))
+      })
+      .collect()
+  }
+  pub fn page_size(&self) -> usize {
+    self.rows_per_page.max(1)
+  }
+  pub fn page_rows(&self) -> Vec<usize> {
+    self
+      .visible_rows()
+      .into_iter()
+      .skip(self.current_page.saturating_mul(self.page_size()))
+      .take(self.page_size())
+      .collect()
+  }
   pub fn total_pages(&self) -> usize {
-    let visible_rows =
-      self.data.iter().skip(self.first_row).filter(|row| self.is_row_visible(row)).count();
-    (visible_rows + self.rows_per_page - 1) / self.rows_per_page
+    self.visible_rows().len().div_ceil(self.page_size())
+  }
+  pub fn cursor_cell(&self) -> Option<(&str, &str)> {
+    let row_idx = *self.page_rows().get(self.selected_row)?;
+    let col_idx = *self.visible_columns().get(self.selected_column)?;
+    let name = self.data.get(self.first_row)?.get(col_idx)?;
+    let value = self.data[row_idx].get(col_idx)?;
+    Some((name, value))
+  }
+  pub fn cursor_position(&self) -> (usize, usize) {
+    (self.current_page * self.page_size() + self.selected_row, self.selected_column)
+  }
+  pub fn search_matches(&self) -> Vec<(usize, usize)> {
+    let needle = self.data_search.value().to_lowercase();
+    if needle.is_empty() {
+      return Vec::new();
+    }
+    let visible_columns = self.visible_columns();
+    let mut matches = Vec::new();
+    for (pos, row_idx) in self.visible_rows().into_iter().enumerate() {
+      for (i, &col_idx) in visible_columns.iter().enumerate() {
+        if self.data[row_idx].get(col_idx).is_some_and(|s| s.to_lowercase().contains(&needle)) {
+          matches.push((pos, i));
+        }
+      }
+    }
+    matches
+  }
+  pub fn file_stem(&self) -> String {
+    Path::new(&self.original_filename)
+      .file_stem()
+      .and_then(|s| s.to_str())
+      .unwrap_or(&self.original_filename)
+      .to_string()
+  }
+  pub fn resolve_template(&self, template: &str) -> String {
+    let sheet = self.selected_sheet.map_or("export", |idx| self.sheets[idx].as_str());
+    normalize_text(
+      &template
+        .replace("{sheet}", sheet.trim_matches(['[', ']']))
+        .replace("{file}", &self.file_stem())
+        .replace("{date}", &Local::now().format("%Y-%m-%d").to_string()),
+    )
   }
   pub fn get_default_filename(&self) -> String {
-    if self.sheets.first().map(|s| s.as_str()) == Some("[Merged]") {
-      Path::new(&self.original_filename)
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or(&self.original_filename)
-        .to_string()
+    if !self.filename_template.value().trim().is_empty() {
+      self.resolve_template(self.filename_template.value())
+    } else if self.sheets.first().map(|s| s.as_str()) == Some("[Merged]") {
+      self.file_stem()
     } else {
       self
         .selected_sheet
@@ -119,39 +987,419 @@ impl App {
         .unwrap_or_else(|| "export".to_string())
     }
   }
-  pub fn create_json_records(&self) -> Vec<serde_json::Value> {
-    let visible_columns = self.visible_columns();
+  pub fn export_rows(&self) -> Vec<usize> {
+    let from = self.rows_from.value().trim().parse::<usize>().map_or(0, |n| n.saturating_sub(1));
+    let to = self.rows_to.value().trim().parse::<usize>().ok();
+    let rows = self.visible_rows().into_iter().skip(from);
+    let rows: Vec<usize> = match to {
+      Some(to) => rows.take(to.saturating_sub(from)).collect(),
+      None => rows.collect(),
+    };
+    rows.into_iter().filter(|&row_idx| self.row_exported(row_idx)).collect()
+  }
+  // Marked rows are left out, or are the only rows kept
+  pub fn row_exported(&self, row_idx: usize) -> bool {
+    self.marked_rows.contains(&row_idx) == self.keep_marked
+  }
+  pub fn validation_errors(&self) -> Result<Vec<String>, String> {
+    let rows = self.export_rows();
+    let mut errors = Vec::new();
+    for col_idx in self.export_columns() {
+      let config = &self.column_configs[col_idx];
+      let name = &self.data[self.first_row][col_idx];
+      let bound = |input: &Input, label: &str| -> Result<Option<f64>, String> {
+        let text = input.value().trim();
+        if text.is_empty() {
+          return Ok(None);
+        }
+        parse_number(text).map(Some).ok_or(format!("Invalid {} '{}' for {}", label, text, name))
+      };
+      let min = bound(&config.min, "min")?;
+      let max = bound(&config.max, "max")?;
+      let pattern = match config.pattern.value() {
+        "" => None,
+        p => Some(Regex::new(p).map_err(|_| format!("Invalid regex '{}' for {}", p, name))?),
+      };
+      config
+        .affix_pattern()
+        .map_err(|_| format!("Invalid affix regex '{}' for {}", config.affix_test.value(), name))?;
+      if !valid_date_format(config.date_format.value()) {
+        return Err(format!("Invalid date format '{}' for {}", config.date_format.value(), name));
+      }
+      let (mut missing, mut out_of_range, mut mismatched) = (0, 0, 0);
+      for &row_idx in &rows {
+        let cell = self.data[row_idx]
+          .get(col_idx)
+          .and_then(|s| self.normalize(col_idx, s))
+          .unwrap_or_default();
+        let cell = cell.trim();
+        if cell.is_empty() {
+          missing += config.required as usize;
+          continue;
+        }
+        if min.is_some() || max.is_some() {
+          match parse_number(cell) {
+            Some(n) if min.is_none_or(|m| n >= m) && max.is_none_or(|m| n <= m) => {}
+            _ => out_of_range += 1,
+          }
+        }
+        if pattern.as_ref().is_some_and(|re| !re.is_match(cell)) {
+          mismatched += 1;
+        }
+      }
+      for (count, rule) in [(missing, "required"), (out_of_range, "range"), (mismatched, "regex")] {
+        if count > 0 {
+          errors.push(format!("{} rows failed {} on {}", count, rule, name));
+        }
+      }
+    }
+    Ok(errors)
+  }
+  pub fn export_columns(&self) -> Vec<usize> {
     self
-      .data
-      .iter()
-      .skip(self.first_row + 1)
-      .filter(|row| self.is_row_visible(row))
-      .map(|row| {
-        visible_columns
-          .iter()
-          .filter_map(|&col_idx| {
-            let value = row.get(col_idx)?;
-            let config = &self.column_configs[col_idx];
-            let field_name = if self.custom_keys[col_idx].value().is_empty() {
-              &self.data[self.first_row][col_idx]
-            } else {
-              self.custom_keys[col_idx].value()
-            };
-            Some((
-              normalize_text(field_name),
-              serde_json::Value::String(format!(
-                "{}{}{}",
-                config.prefix.value(),
-                value,
-                config.postfix.value()
-              )),
-            ))
-          })
-          .collect::<serde_json::Map<String, serde_json::Value>>()
-          .into()
+      .visible_columns()
+      .into_iter()
+      .filter(|&i| !self.column_configs[i].exclude)
+      .filter(|&i| !(self.drop_partition && self.partition_column == Some(i)))
+      .collect()
+  }
+  pub fn field_name(&self, col_idx: usize) -> String {
+    let key = if self.custom_keys[col_idx].value().is_empty() {
+      &self.data[self.first_row][col_idx]
+    } else {
+      self.custom_keys[col_idx].value()
+    };
+    // Columns without a header, such as cells past the header's end, would all share ""
+    if key.trim().is_empty() {
+      return format!("column_{}", col_idx + 1);
+    }
+    if self.verbatim_keys {
+      key.to_string()
+    } else {
+      normalize_text(key)
+    }
+  }
+  pub fn create_json_records(&self) -> Vec<serde_json::Value> {
+    self.records_for_rows(&self.export_rows())
+  }
+  pub fn json_indent(&self) -> Option<&'static [u8]> {
+    (!self.compact).then(|| self.indent.bytes())
+  }
+  pub fn matrix_output(&self) -> bool {
+    self.matrix
+      && matches!(self.export_format, ExportFormat::Json | ExportFormat::Yaml | ExportFormat::Toml)
+  }
+  pub fn records_for_rows(&self, rows: &[usize]) -> Vec<serde_json::Value> {
+    self.records_with_progress(rows, &mut |_| true).unwrap_or_default()
+  }
+  pub fn records_with_progress(
+    &self,
+    rows: &[usize],
+    progress: &mut dyn FnMut(usize) -> bool,
+  ) -> Option<Vec<serde_json::Value>> {
+    let columns = self.export_columns();
+    let patterns: Vec<Option<Regex>> =
+      self.column_configs.iter().map(|config| config.affix_pattern().ok().flatten()).collect();
+    let types = self.value_types(rows);
+    let create_record =
+      |row_idx: usize, columns: &[usize]| self.create_record(row_idx, columns, &patterns, &types);
+    let Some(group_col) = self.group_column.filter(|_| !self.matrix_output()) else {
+      let mut records = Vec::with_capacity(rows.len());
+      for chunk in rows.chunks(PROGRESS_CHUNK) {
+        records.extend(chunk.iter().map(|&row_idx| create_record(row_idx, &columns)));
+        if !progress(records.len()) {
+          return None;
+        }
+      }
+      return Some(records);
+    };
+    let item_columns: Vec<usize> = columns.into_iter().filter(|&c| c != group_col).collect();
+    let items_key = match self.items_key.value().trim() {
+      "" => "items".to_string(),
+      key => key.to_string(),
+    };
+    let groups = group_ordered(rows.iter().copied(), |&row_idx| {
+      create_record(row_idx, &[group_col]).to_string()
+    });
+    let mut records = Vec::with_capacity(groups.len());
+    let mut done = 0;
+    for (_, group_rows) in groups {
+      let mut record = match create_record(group_rows[0], &[group_col]) {
+        serde_json::Value::Object(map) => map,
+        _ => serde_json::Map::new(),
+      };
+      let items = group_rows.iter().map(|&row_idx| create_record(row_idx, &item_columns));
+      record.insert(items_key.clone(), serde_json::Value::Array(items.collect()));
+      records.push(record.into());
+      let before = done;
+      done += group_rows.len();
+      if done / PROGRESS_CHUNK != before / PROGRESS_CHUNK && !progress(done) {
+        return None;
+      }
+    }
+    Some(records)
+  }
+  pub fn manifest(&self, records: usize) -> serde_json::Value {
+    let sheets: Vec<serde_json::Value> = if self.merged_from.is_empty() {
+      self
+        .selected_sheet
+        .map(
+          |idx| serde_json::json!({ "name": self.sheets[idx], "header_row": self.first_row + 1 }),
+        )
+        .into_iter()
+        .collect()
+    } else {
+      self
+        .merged_from
+        .iter()
+        .map(|(name, header_row)| serde_json::json!({ "name": name, "header_row": header_row + 1 }))
+        .collect()
+    };
+    let columns: Vec<serde_json::Value> = self
+      .export_columns()
+      .into_iter()
+      .map(|col_idx| {
+        serde_json::json!({
+          "column": self.data[self.first_row][col_idx],
+          "key": self.field_name(col_idx),
+        })
+      })
+      .collect();
+    let source = std::fs::canonicalize(&self.original_filename)
+      .map_or(self.original_filename.clone(), |path| path.to_string_lossy().to_string());
+    serde_json::json!({
+      "source": source,
+      "sheets": sheets,
+      "columns": columns,
+      "format": self.export_format.extension(),
+      "deduplicate": self.deduplicate,
+      "records": records,
+      "exported_at": Local::now().to_rfc3339(),
+    })
+  }
+  pub fn value_types(&self, rows: &[usize]) -> Vec<Option<ColumnType>> {
+    let columns = self.export_columns();
+    (0..self.column_configs.len())
+      .map(|col_idx| match self.column_configs[col_idx].value_type {
+        Some(value_type) => Some(value_type),
+        None if self.typed_values && columns.contains(&col_idx) => {
+          let values: Vec<Cow<str>> = rows
+            .iter()
+            .filter_map(|&r| self.data[r].get(col_idx))
+            .filter_map(|s| self.normalize(col_idx, s))
+            .collect();
+          Some(infer_type(values.iter().map(|s| s.as_ref())))
+        }
+        None => None,
       })
       .collect()
   }
+  pub fn normalize<'a>(&self, col_idx: usize, value: &'a str) -> Option<Cow<'a, str>> {
+    let config = &self.column_configs[col_idx];
+    let value = match config.strip(value) {
+      value if !config.trim.unwrap_or(self.trim_values) => value,
+      Cow::Borrowed(value) => Cow::Borrowed(value.trim()),
+      Cow::Owned(value) => Cow::Owned(value.trim().to_string()),
+    };
+    let tokens = match config.nulls.value().trim() {
+      "" => self.null_tokens.value(),
+      tokens => tokens,
+    };
+    let test = value.trim();
+    let is_null = tokens
+      .split(',')
+      .map(str::trim)
+      .filter(|token| !token.is_empty())
+      .any(|token| token == test || (token == "\"\"" && test.is_empty()));
+    (!is_null).then_some(value)
+  }
+  pub fn create_record(
+    &self,
+    row_idx: usize,
+    columns: &[usize],
+    patterns: &[Option<Regex>],
+    types: &[Option<ColumnType>],
+  ) -> serde_json::Value {
+    let row = &self.data[row_idx];
+    columns
+      .iter()
+      .filter_map(|&col_idx| {
+        let config = &self.column_configs[col_idx];
+        let Some(value) = self.normalize(col_idx, row.get(col_idx)?) else {
+          return Some((self.field_name(col_idx), serde_json::Value::Null));
+        };
+        let value = value.as_ref();
+        let (prefix, postfix) = if config.decorates(value, patterns[col_idx].as_ref()) {
+          (config.prefix.value(), config.postfix.value())
+        } else {
+          ("", "")
+        };
+        let affixed = !prefix.is_empty() || !postfix.is_empty();
+        let typed = config
+          .map_bool(value)
+          .or_else(|| config.number_format.apply(value))
+          .or_else(|| types[col_idx].and_then(|value_type| value_type.apply(value)));
+        let value = match typed {
+          Some(serde_json::Value::Null) => serde_json::Value::Null,
+          Some(number) if !affixed || !config.affix_typed => number,
+          Some(number) => serde_json::Value::String(format!("{}{}{}", prefix, number, postfix)),
+          None => {
+            let value = config.format_date(value).map_or(Cow::Borrowed(value), Cow::Owned);
+            serde_json::Value::String(format!("{}{}{}", prefix, value, postfix))
+          }
+        };
+        Some((self.field_name(col_idx), value))
+      })
+      .collect::<serde_json::Map<String, serde_json::Value>>()
+      .into()
+  }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+  Bool,
+  Int,
+  Float,
+  Date,
+  String,
+}
+
+impl ColumnType {
+  pub fn label(&self) -> &'static str {
+    match self {
+      ColumnType::Bool => "bool",
+      ColumnType::Int => "int",
+      ColumnType::Float => "float",
+      ColumnType::Date => "date",
+      ColumnType::String => "string",
+    }
+  }
+  pub fn next_override(current: Option<Self>) -> Option<Self> {
+    match current {
+      None => Some(ColumnType::String),
+      Some(ColumnType::String) => Some(ColumnType::Int),
+      Some(ColumnType::Int) => Some(ColumnType::Float),
+      Some(ColumnType::Float) => Some(ColumnType::Bool),
+      Some(ColumnType::Bool) => Some(ColumnType::Date),
+      Some(ColumnType::Date) => None,
+    }
+  }
+  pub fn apply(&self, value: &str) -> Option<serde_json::Value> {
+    let value = value.trim();
+    if value.is_empty() {
+      return Some(serde_json::Value::Null);
+    }
+    match self {
+      ColumnType::Bool if value.eq_ignore_ascii_case("true") => Some(true.into()),
+      ColumnType::Bool if value.eq_ignore_ascii_case("false") => Some(false.into()),
+      ColumnType::Int => value.parse::<i64>().ok().map(Into::into),
+      ColumnType::Float => {
+        value.parse::<f64>().ok().and_then(serde_json::Number::from_f64).map(Into::into)
+      }
+      _ => None,
+    }
+  }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum TableLayout {
+  Fit,
+  Full,
+  Equal,
+}
+
+impl TableLayout {
+  pub fn label(&self) -> &'static str {
+    match self {
+      TableLayout::Fit => "fit",
+      TableLayout::Full => "full",
+      TableLayout::Equal => "equal",
+    }
+  }
+  pub fn next(&self) -> Self {
+    match self {
+      TableLayout::Fit => TableLayout::Full,
+      TableLayout::Full => TableLayout::Equal,
+      TableLayout::Equal => TableLayout::Fit,
+    }
+  }
+}
+
+#[derive(PartialEq, Copy, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Indent {
+  #[default]
+  Two,
+  Four,
+  Tab,
+}
+
+impl Indent {
+  pub fn label(&self) -> &'static str {
+    match self {
+      Indent::Two => "2",
+      Indent::Four => "4",
+      Indent::Tab => "tab",
+    }
+  }
+  pub fn next(&self) -> Self {
+    match self {
+      Indent::Two => Indent::Four,
+      Indent::Four => Indent::Tab,
+      Indent::Tab => Indent::Two,
+    }
+  }
+  pub fn bytes(&self) -> &'static [u8] {
+    match self {
+      Indent::Two => b"  ",
+      Indent::Four => b"    ",
+      Indent::Tab => b"\t",
+    }
+  }
+  pub fn from_label(label: &str) -> Option<Self> {
+    [Indent::Two, Indent::Four, Indent::Tab].into_iter().find(|indent| indent.label() == label)
+  }
+}
+
+#[derive(PartialEq, Copy, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SqlDialect {
+  #[default]
+  Sqlite,
+  Postgres,
+  Mysql,
+}
+
+impl SqlDialect {
+  pub fn label(&self) -> &'static str {
+    match self {
+      SqlDialect::Sqlite => "sqlite",
+      SqlDialect::Postgres => "postgres",
+      SqlDialect::Mysql => "mysql",
+    }
+  }
+  pub fn next(&self) -> Self {
+    match self {
+      SqlDialect::Sqlite => SqlDialect::Postgres,
+      SqlDialect::Postgres => SqlDialect::Mysql,
+      SqlDialect::Mysql => SqlDialect::Sqlite,
+    }
+  }
+  pub fn from_label(label: &str) -> Option<Self> {
+    [SqlDialect::Sqlite, SqlDialect::Postgres, SqlDialect::Mysql]
+      .into_iter()
+      .find(|dialect| dialect.label() == label)
+  }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum Popup {
+  Cell,
+  Records,
+  Stats,
+  Confirm,
+  ConfirmReset,
 }
 
 #[derive(PartialEq, Copy, Clone)]
@@ -160,15 +1408,260 @@ pub enum Step {
   RowTrim,
   MergePrompt,
   ColSelect,
+  Join,
   Preview,
   Export,
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+  Json,
+  Ndjson,
+  Yaml,
+  Toml,
+  Sql,
+  Xml,
+  Html,
+  Csv,
+  #[cfg(feature = "xlsx")]
+  Xlsx,
+  #[cfg(feature = "parquet")]
+  Parquet,
+  #[cfg(feature = "sqlite")]
+  Sqlite,
+}
+
+pub const TEMPLATE_PLACEHOLDERS: &str = "{sheet} {file} {date}";
+
+impl ExportFormat {
+  pub fn extension(self) -> &'static str {
+    match self {
+      ExportFormat::Json => "json",
+      ExportFormat::Ndjson => "jsonl",
+      ExportFormat::Yaml => "yaml",
+      ExportFormat::Toml => "toml",
+      ExportFormat::Sql => "sql",
+      ExportFormat::Xml => "xml",
+      ExportFormat::Html => "html",
+      ExportFormat::Csv => "csv",
+      #[cfg(feature = "xlsx")]
+      ExportFormat::Xlsx => "xlsx",
+      #[cfg(feature = "parquet")]
+      ExportFormat::Parquet => "parquet",
+      #[cfg(feature = "sqlite")]
+      ExportFormat::Sqlite => "sqlite",
+    }
+  }
+  pub fn from_extension(name: &str) -> Option<Self> {
+    if name.eq_ignore_ascii_case("yml") {
+      return Some(ExportFormat::Yaml);
+    }
+    let mut format = ExportFormat::Json;
+    loop {
+      if format.extension().eq_ignore_ascii_case(name) {
+        return Some(format);
+      }
+      format = format.next();
+      if format == ExportFormat::Json {
+        return None;
+      }
+    }
+  }
+  pub fn has_table(self) -> bool {
+    match self {
+      ExportFormat::Sql => true,
+      #[cfg(feature = "sqlite")]
+      ExportFormat::Sqlite => true,
+      #[cfg(feature = "xlsx")]
+      ExportFormat::Xlsx => true,
+      _ => false,
+    }
+  }
+  pub fn is_text(self) -> bool {
+    match self {
+      #[cfg(feature = "parquet")]
+      ExportFormat::Parquet => false,
+      #[cfg(feature = "sqlite")]
+      ExportFormat::Sqlite => false,
+      #[cfg(feature = "xlsx")]
+      ExportFormat::Xlsx => false,
+      _ => true,
+    }
+  }
+  pub fn next(self) -> Self {
+    match self {
+      ExportFormat::Json => ExportFormat::Ndjson,
+      ExportFormat::Ndjson => ExportFormat::Yaml,
+      ExportFormat::Yaml => ExportFormat::Toml,
+      ExportFormat::Toml => ExportFormat::Sql,
+      #[cfg(feature = "sqlite")]
+      ExportFormat::Sql => ExportFormat::Sqlite,
+      #[cfg(feature = "sqlite")]
+      ExportFormat::Sqlite => ExportFormat::Xml,
+      #[cfg(not(feature = "sqlite"))]
+      ExportFormat::Sql => ExportFormat::Xml,
+      ExportFormat::Xml => ExportFormat::Html,
+      ExportFormat::Html => ExportFormat::Csv,
+      #[cfg(feature = "xlsx")]
+      ExportFormat::Csv => ExportFormat::Xlsx,
+      #[cfg(all(feature = "xlsx", feature = "parquet"))]
+      ExportFormat::Xlsx => ExportFormat::Parquet,
+      #[cfg(all(feature = "xlsx", not(feature = "parquet")))]
+      ExportFormat::Xlsx => ExportFormat::Json,
+      #[cfg(all(not(feature = "xlsx"), feature = "parquet"))]
+      ExportFormat::Csv => ExportFormat::Parquet,
+      #[cfg(feature = "parquet")]
+      ExportFormat::Parquet => ExportFormat::Json,
+      #[cfg(not(any(feature = "xlsx", feature = "parquet")))]
+      ExportFormat::Csv => ExportFormat::Json,
+    }
+  }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum RowTrimEdit {
+  FirstRow,
+  LastRow,
+  Separator,
+}
+
+impl RowTrimEdit {
+  pub fn next(&self) -> Self {
+    match self {
+      RowTrimEdit::FirstRow => RowTrimEdit::LastRow,
+      RowTrimEdit::LastRow => RowTrimEdit::Separator,
+      RowTrimEdit::Separator => RowTrimEdit::FirstRow,
+    }
+  }
+}
+
+#[derive(PartialEq, Copy, Clone)]
 pub enum ExportEdit {
   FileName,
+  Template,
+  OutDir,
+  VerbatimKeys,
+  TrimValues,
+  NullTokens,
+  TypedValues,
+  Format,
+  TableName,
+  SqlDialect,
+  XmlRoot,
+  XmlRecord,
   Deduplicate,
+  NormalizeDedup,
+  Append,
+  Schema,
+  Manifest,
+  Matrix,
+  Bom,
+  Crlf,
+  Indent,
+  Compact,
+  Partition,
+  DropPartition,
+  RowsFrom,
+  RowsTo,
+  GroupBy,
+  ItemsKey,
   KeyStr,
   Prefix,
   Postfix,
+  Required,
+  DedupKey,
+  NumberFormat,
+  Min,
+  Max,
+  Pattern,
+  BoolMap,
+  Truthy,
+  Falsy,
+  AffixTyped,
+  AffixWhen,
+  AffixTest,
+  TrimChars,
+  TrimAll,
+  Trim,
+  Nulls,
+  ValueType,
+  DateFormat,
+  Exclude,
+}
+
+impl ExportEdit {
+  pub const GLOBAL: &'static [ExportEdit] = &[
+    ExportEdit::FileName,
+    ExportEdit::Template,
+    ExportEdit::OutDir,
+    ExportEdit::VerbatimKeys,
+    ExportEdit::TrimValues,
+    ExportEdit::NullTokens,
+    ExportEdit::TypedValues,
+    ExportEdit::Format,
+    ExportEdit::TableName,
+    ExportEdit::SqlDialect,
+    ExportEdit::XmlRoot,
+    ExportEdit::XmlRecord,
+    ExportEdit::Deduplicate,
+    ExportEdit::NormalizeDedup,
+    ExportEdit::Append,
+    ExportEdit::Schema,
+    ExportEdit::Manifest,
+    ExportEdit::Matrix,
+    ExportEdit::Bom,
+    ExportEdit::Crlf,
+    ExportEdit::Indent,
+    ExportEdit::Compact,
+    ExportEdit::Partition,
+    ExportEdit::DropPartition,
+    ExportEdit::RowsFrom,
+    ExportEdit::RowsTo,
+    ExportEdit::GroupBy,
+    ExportEdit::ItemsKey,
+  ];
+  pub const COLUMN: &'static [ExportEdit] = &[
+    ExportEdit::KeyStr,
+    ExportEdit::Prefix,
+    ExportEdit::Postfix,
+    ExportEdit::Exclude,
+    ExportEdit::TrimChars,
+    ExportEdit::TrimAll,
+    ExportEdit::Trim,
+    ExportEdit::Nulls,
+    ExportEdit::ValueType,
+    ExportEdit::DateFormat,
+    ExportEdit::AffixWhen,
+    ExportEdit::AffixTest,
+    ExportEdit::Required,
+    ExportEdit::DedupKey,
+    ExportEdit::NumberFormat,
+    ExportEdit::Min,
+    ExportEdit::Max,
+    ExportEdit::Pattern,
+    ExportEdit::BoolMap,
+    ExportEdit::Truthy,
+    ExportEdit::Falsy,
+    ExportEdit::AffixTyped,
+  ];
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn affix_typed_applies_to_every_typed_column() {
+    let mut config = ColumnConfig::default();
+    assert!(!config.applies(ExportEdit::AffixTyped, false));
+    assert!(config.applies(ExportEdit::AffixTyped, true));
+    config.value_type = Some(ColumnType::Int);
+    assert!(config.applies(ExportEdit::AffixTyped, false));
+    config.value_type = Some(ColumnType::String);
+    assert!(!config.applies(ExportEdit::AffixTyped, true));
+    config.value_type = None;
+    config.number_format = NumberFormat::Integer;
+    assert!(config.applies(ExportEdit::AffixTyped, false));
+  }
 }
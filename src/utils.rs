@@ -1,17 +1,36 @@
-use crate::types::{App, ColumnState};
-use comfy_table::{presets::UTF8_FULL, ContentArrangement, Table};
+use crate::types::{App, ColumnType, TableLayout, MATCH_STYLE};
+use chrono::{format::Item, format::StrftimeItems, NaiveDate, NaiveDateTime, NaiveTime};
 use crossterm::event::KeyCode::{self, Down, Up};
 use ratatui::{
   layout::{Constraint, Direction, Layout, Rect},
+  style::{Color, Modifier, Style},
+  text::{Line, Span},
+  widgets::{Block, Borders, Cell, HighlightSpacing, Row, Table, TableState},
   Frame,
 };
+use std::{cmp::Ordering, collections::HashMap};
 use unicode_width::UnicodeWidthStr;
 use unidecode::unidecode;
 
+const FIT_MAX_WIDTH: u16 = 40;
+const MAX_EXACT_DIGITS: usize = 15;
+
 pub fn normalize_text(text: &str) -> String {
   unidecode(text).replace([' ', '-'], "_").to_lowercase()
 }
 
+// Keeps only characters that are safe in a file name, so cell values cannot leave the directory
+pub fn safe_filename(text: &str) -> String {
+  let name: String = normalize_text(text)
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '_' })
+    .collect();
+  match name.trim_matches(['.', '_']) {
+    "" => "unknown".to_string(),
+    name => name.to_string(),
+  }
+}
+
 pub fn navigate_index(cur: usize, len: usize, key: KeyCode) -> usize {
   match key {
     Down => (cur + 1) % len,
@@ -20,41 +39,402 @@ pub fn navigate_index(cur: usize, len: usize, key: KeyCode) -> usize {
   }
 }
 
+// A row index or an inclusive range such as "2-3" or "2..3"
+pub fn parse_row_range(text: &str) -> Option<(usize, usize)> {
+  let text = text.trim();
+  let (first, last) =
+    text.split_once("..").or_else(|| text.split_once('-')).unwrap_or((text, text));
+  let (first, last) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+  (first <= last).then_some((first, last))
+}
+
+pub fn follow_scroll(offset: usize, selected: usize, height: u16) -> usize {
+  let height = (height as usize).max(1);
+  if selected < offset {
+    selected
+  } else if selected >= offset + height {
+    selected + 1 - height
+  } else {
+    offset
+  }
+}
+
+pub fn group_ordered<T>(
+  items: impl IntoIterator<Item = T>,
+  key: impl Fn(&T) -> String,
+) -> Vec<(String, Vec<T>)> {
+  let mut groups: Vec<(String, Vec<T>)> = Vec::new();
+  let mut index: HashMap<String, usize> = HashMap::new();
+  for item in items {
+    let k = key(&item);
+    let idx = *index.entry(k.clone()).or_insert_with(|| {
+      groups.push((k, Vec::new()));
+      groups.len() - 1
+    });
+    groups[idx].1.push(item);
+  }
+  groups
+}
+
+pub fn parse_number(text: &str) -> Option<f64> {
+  let text = text.trim();
+  let number: f64 = if text.contains(',') {
+    // Commas only group the integer part, so "1.234,5" is not read as 1.2345
+    let (int_part, fraction) = text.split_once('.').unwrap_or((text, ""));
+    let mut groups = int_part.strip_prefix('-').unwrap_or(int_part).split(',');
+    let first = groups.next()?;
+    let digits = |g: &str| g.chars().all(|c| c.is_ascii_digit());
+    if fraction.contains(',')
+      || !(1..=3).contains(&first.len())
+      || !digits(first)
+      || !groups.all(|g| g.len() == 3 && digits(g))
+    {
+      return None;
+    }
+    text.replace(',', "").parse().ok()?
+  } else {
+    text.parse().ok()?
+  };
+  number.is_finite().then_some(number)
+}
+
+pub fn compare_cells(a: &str, b: &str) -> Ordering {
+  match (parse_number(a), parse_number(b)) {
+    (Some(a), Some(b)) => a.total_cmp(&b),
+    (Some(_), None) => Ordering::Less,
+    (None, Some(_)) => Ordering::Greater,
+    (None, None) => a.to_lowercase().cmp(&b.to_lowercase()),
+  }
+}
+
+pub fn parse_date(text: &str) -> Option<NaiveDateTime> {
+  let text = text.trim();
+  ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M:%S%.f"]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+    .or_else(|| {
+      NaiveDate::parse_from_str(text, "%Y-%m-%d").ok().map(|date| date.and_time(NaiveTime::MIN))
+    })
+}
+
+pub fn valid_date_format(format: &str) -> bool {
+  !StrftimeItems::new(format).any(|item| matches!(item, Item::Error))
+}
+
+pub fn infer_type<'a>(values: impl IntoIterator<Item = &'a str>) -> ColumnType {
+  let is_number = |s: &str| {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let significant = digits.chars().filter(char::is_ascii_digit).skip_while(|&c| c == '0');
+    digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+      && !(digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0."))
+      && significant.count() <= MAX_EXACT_DIGITS
+  };
+  let mut inferred = None;
+  for value in values.into_iter().map(str::trim).filter(|s| !s.is_empty()) {
+    let value_type = if value.eq_ignore_ascii_case("true") || value.eq_ignore_ascii_case("false") {
+      ColumnType::Bool
+    } else if parse_date(value).is_some() {
+      ColumnType::Date
+    } else if is_number(value) && value.parse::<i64>().is_ok() {
+      ColumnType::Int
+    } else if is_number(value) && value.parse::<f64>().is_ok_and(f64::is_finite) {
+      ColumnType::Float
+    } else {
+      return ColumnType::String;
+    };
+    inferred = Some(match (inferred, value_type) {
+      (None, t) => t,
+      (Some(a), b) if a == b => a,
+      (Some(ColumnType::Int), ColumnType::Float) | (Some(ColumnType::Float), ColumnType::Int) => {
+        ColumnType::Float
+      }
+      _ => return ColumnType::String,
+    });
+  }
+  inferred.unwrap_or(ColumnType::String)
+}
+
+// Pairs primary columns with a header's cells: exact names first, then names that only differ
+// in case, accents, spacing or punctuation ("País " and "pais"), then, with a max_distance,
+// names within that many edits of each other
+pub fn pair_columns(
+  primary: &[String],
+  header: &[String],
+  max_distance: usize,
+) -> HashMap<String, String> {
+  let loose = |s: &str| normalize_text(s).chars().filter(char::is_ascii_alphanumeric).collect();
+  let primary: Vec<&str> = primary.iter().map(|s| s.trim()).filter(|s| !s.is_empty()).collect();
+  let header: Vec<&str> = header.iter().map(|s| s.trim()).filter(|s| !s.is_empty()).collect();
+  let mut pairs: HashMap<String, String> = HashMap::new();
+  for distance in [0, max_distance] {
+    for &column in &primary {
+      if pairs.contains_key(column) {
+        continue;
+      }
+      let key: String = loose(column);
+      let free =
+        |cell: &&&str| !primary.contains(cell) && !pairs.values().any(|paired| paired == **cell);
+      let matched = header.iter().find(|&&cell| cell == column).or_else(|| {
+        let mut similar = header.iter().filter(free).filter(|&&cell| match distance {
+          0 => !key.is_empty() && loose(cell) == key,
+          _ => key.len() > distance * 2 && edit_distance(&loose(cell), &key) <= distance,
+        });
+        similar.next().filter(|_| similar.next().is_none())
+      });
+      if let Some(cell) = matched {
+        pairs.insert(column.to_string(), cell.to_string());
+      }
+    }
+    if max_distance == 0 {
+      break;
+    }
+  }
+  pairs
+}
+
+pub fn edit_distance(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut previous: Vec<usize> = (0..=b.len()).collect();
+  for (i, ca) in a.chars().enumerate() {
+    let mut current = vec![i + 1];
+    for (j, &cb) in b.iter().enumerate() {
+      let cost = usize::from(ca != cb);
+      current.push((previous[j] + cost).min(previous[j + 1] + 1).min(current[j] + 1));
+    }
+    previous = current;
+  }
+  previous[b.len()]
+}
+
+// Stacked header rows joined into one row; an empty cell in an upper row repeats the
+// cell to its left, as merged cells read back
+pub fn join_header_rows(rows: &[Vec<String>], separator: &str) -> Vec<String> {
+  let mut parts: Vec<Vec<String>> = Vec::new();
+  for (row_idx, cells) in rows.iter().enumerate() {
+    let mut previous = "";
+    for (col_idx, cell) in cells.iter().enumerate() {
+      let mut cell = cell.trim();
+      if cell.is_empty() && row_idx + 1 < rows.len() {
+        cell = previous;
+      }
+      previous = cell;
+      if col_idx == parts.len() {
+        parts.push(Vec::new());
+      }
+      if !cell.is_empty() {
+        parts[col_idx].push(cell.to_string());
+      }
+    }
+  }
+  parts.iter().map(|part| part.join(separator)).collect()
+}
+
+pub fn header_cells(row: &[String]) -> Vec<String> {
+  let mut cells: Vec<String> = Vec::new();
+  for cell in row.iter().map(|s| s.trim()).filter(|s| !s.is_empty()) {
+    if !cells.iter().any(|c| c == cell) {
+      cells.push(cell.to_string());
+    }
+  }
+  cells
+}
+
+pub fn display_cell(text: &str) -> String {
+  text.replace("\r\n", "⏎").replace(['\n', '\r'], "⏎").replace('\t', "⇥")
+}
+
 pub fn visual_width(text: &str) -> usize {
   UnicodeWidthStr::width(text)
 }
 
-pub fn layout(f: &mut Frame) -> (Rect, Rect, Rect) {
+pub fn layout(f: &mut Frame, header_height: u16) -> (Rect, Rect, Rect) {
   let chunks = Layout::default()
     .direction(Direction::Vertical)
-    .constraints([Constraint::Length(1), Constraint::Min(1), Constraint::Length(1)])
+    .constraints([Constraint::Length(header_height), Constraint::Min(1), Constraint::Length(1)])
     .split(f.area());
   (chunks[0], chunks[1], chunks[2])
 }
 
-pub fn create_table(app: &App, width: u16) -> Table {
-  let mut table = Table::new();
-  table
-    .load_preset(UTF8_FULL)
-    .set_content_arrangement(ContentArrangement::Dynamic)
-    .set_width(width);
+pub fn highlight_matches(text: &str, needle: &str, style: Style) -> Line<'static> {
+  let needle: Vec<char> = needle.to_lowercase().chars().collect();
+  if needle.is_empty() {
+    return Line::raw(text.to_string());
+  }
+  let chars: Vec<char> = text.chars().collect();
+  let mut spans = Vec::new();
+  let (mut start, mut i) = (0, 0);
+  while i + needle.len() <= chars.len() {
+    let matched =
+      chars[i..i + needle.len()].iter().flat_map(|c| c.to_lowercase()).eq(needle.iter().copied());
+    if matched {
+      spans.push(Span::raw(chars[start..i].iter().collect::<String>()));
+      spans.push(Span::styled(chars[i..i + needle.len()].iter().collect::<String>(), style));
+      i += needle.len();
+      start = i;
+    } else {
+      i += 1;
+    }
+  }
+  spans.push(Span::raw(chars[start..].iter().collect::<String>()));
+  Line::from(spans)
+}
+
+fn column_width(app: &App, col_idx: usize) -> u16 {
+  let width = std::iter::once(app.first_row)
+    .chain(app.page_rows())
+    .filter_map(|r| app.data.get(r))
+    .map(|row| row.get(col_idx).map_or(0, |s| visual_width(&display_cell(s))))
+    .max()
+    .unwrap_or(0);
+  width as u16 + 2
+}
+
+pub fn table_columns(app: &App, width: u16) -> Vec<usize> {
+  let visible_columns = app.visible_columns();
+  let offset = app.column_offset.min(visible_columns.len().saturating_sub(1));
+  let mut remaining = width.saturating_sub(4);
+  let mut columns = Vec::new();
+  for &col_idx in &visible_columns[offset..] {
+    let column_width = match app.table_layout {
+      TableLayout::Full => column_width(app, col_idx),
+      TableLayout::Fit | TableLayout::Equal => column_width(app, col_idx).min(FIT_MAX_WIDTH),
+    };
+    if !columns.is_empty() && column_width + 1 > remaining {
+      break;
+    }
+    remaining = remaining.saturating_sub(column_width + 1);
+    columns.push(col_idx);
+  }
+  columns
+}
 
-  let visible_data = app
+pub fn create_table(app: &App, width: u16) -> Table<'static> {
+  let visible_columns = table_columns(app, width);
+  let display = |row: &[String], i: usize| row.get(i).map_or(String::new(), |s| display_cell(s));
+  let page_rows = app.page_rows();
+  let widths = visible_columns.iter().map(|&i| {
+    let width = column_width(app, i);
+    match app.table_layout {
+      TableLayout::Fit => Constraint::Max(width.min(FIT_MAX_WIDTH)),
+      TableLayout::Full => Constraint::Length(width),
+      TableLayout::Equal => Constraint::Fill(1),
+    }
+  });
+  let header = app
     .data
-    .iter()
-    .skip(app.first_row)
-    .filter(|row| app.is_row_visible(row))
-    .skip(app.current_page * app.rows_per_page)
-    .take(app.rows_per_page);
-
-  for row in visible_data {
-    let visible_cells = row
-      .iter()
-      .enumerate()
-      .filter(|(i, _)| matches!(app.columns[*i], ColumnState::Original | ColumnState::NonEmpty))
-      .map(|(_, cell)| cell.to_string())
-      .collect::<Vec<_>>();
-    table.add_row(visible_cells);
-  }
-  table
+    .get(app.first_row)
+    .map(|row| {
+      Row::new(visible_columns.iter().map(|&i| display(row, i)).collect::<Vec<_>>())
+        .style(Style::new().add_modifier(Modifier::BOLD))
+    })
+    .unwrap_or_default();
+  let needle = app.row_filter.value();
+  let search = app.data_search.value();
+  let rows = page_rows.iter().map(|&row_idx| {
+    let cells = visible_columns.iter().map(|&i| {
+      let text = display(&app.data[row_idx], i);
+      if app.row_filter_column == Some(i) {
+        Cell::from(highlight_matches(&text, needle, MATCH_STYLE))
+      } else if !search.is_empty() {
+        Cell::from(highlight_matches(&text, search, MATCH_STYLE))
+      } else {
+        Cell::from(text)
+      }
+    });
+    let row = Row::new(cells.collect::<Vec<_>>());
+    if app.row_exported(row_idx) {
+      row
+    } else {
+      row.style(Style::new().fg(Color::DarkGray).add_modifier(Modifier::CROSSED_OUT))
+    }
+  });
+  Table::new(rows, widths)
+    .header(header)
+    .block(Block::default().borders(Borders::ALL))
+    .column_spacing(1)
+    .highlight_symbol("› ")
+    .highlight_spacing(HighlightSpacing::Always)
+    .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+    .column_highlight_style(Style::new().fg(Color::Yellow))
+    .cell_highlight_style(Style::new().add_modifier(Modifier::BOLD))
+}
+
+pub fn table_state(app: &App) -> TableState {
+  TableState::new()
+    .with_selected(Some(app.selected_row))
+    .with_selected_column(app.selected_column.checked_sub(app.column_offset))
+}
+
+pub fn centered_rect(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
+  let vertical = Layout::default()
+    .direction(Direction::Vertical)
+    .constraints([
+      Constraint::Percentage((100 - percent_y) / 2),
+      Constraint::Percentage(percent_y),
+      Constraint::Percentage((100 - percent_y) / 2),
+    ])
+    .split(area);
+  Layout::default()
+    .direction(Direction::Horizontal)
+    .constraints([
+      Constraint::Percentage((100 - percent_x) / 2),
+      Constraint::Percentage(percent_x),
+      Constraint::Percentage((100 - percent_x) / 2),
+    ])
+    .split(vertical[1])[1]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn safe_filename_strips_path_characters() {
+    assert_eq!(safe_filename("North America"), "north_america");
+    assert_eq!(safe_filename("../etc/passwd"), "etc_passwd");
+    assert_eq!(safe_filename("a\\b\0c"), "a_b_c");
+    assert_eq!(safe_filename(".."), "unknown");
+    assert_eq!(safe_filename("/"), "unknown");
+  }
+
+  #[test]
+  fn display_cell_keeps_multi_line_cells_on_one_line() {
+    assert_eq!(display_cell("a\nb"), "a⏎b");
+    assert_eq!(display_cell("a\r\nb"), "a⏎b");
+    assert_eq!(display_cell("a\rb\tc"), "a⏎b⇥c");
+    assert_eq!(display_cell("plain"), "plain");
+  }
+
+  #[test]
+  fn display_cell_width_counts_one_column_per_line_break() {
+    assert_eq!(visual_width(&display_cell("line one\r\nline two")), 17);
+    let long = "word\n".repeat(20);
+    assert_eq!(visual_width(&display_cell(&long)), 100);
+    assert_eq!((visual_width(&display_cell(&long)) as u16).min(FIT_MAX_WIDTH), FIT_MAX_WIDTH);
+  }
+
+  #[test]
+  fn infer_type_keeps_values_that_numbers_would_mangle() {
+    assert_eq!(infer_type(["007"]), ColumnType::String);
+    assert_eq!(infer_type(["1", "07030"]), ColumnType::String);
+    assert_eq!(infer_type(["12345678901234567890"]), ColumnType::String);
+    assert_eq!(infer_type(["1e5"]), ColumnType::String);
+    assert_eq!(infer_type(["0.5"]), ColumnType::Float);
+    assert_eq!(infer_type(["0", "-12"]), ColumnType::Int);
+    assert_eq!(infer_type(["1", "2.5", ""]), ColumnType::Float);
+  }
+
+  #[test]
+  fn parse_number_reads_grouped_and_signed_numbers() {
+    assert_eq!(parse_number("12abc"), None);
+    assert_eq!(parse_number("1,000"), Some(1000.0));
+    assert_eq!(parse_number("-1,234.5"), Some(-1234.5));
+    assert_eq!(parse_number("1,00"), None);
+    assert_eq!(parse_number("1.234,5"), None);
+    assert_eq!(parse_number("1,234.5,6"), None);
+    assert_eq!(parse_number(" -3.5 "), Some(-3.5));
+    assert_eq!(parse_number("-"), None);
+    assert_eq!(parse_number("$"), None);
+    assert_eq!(parse_number("$12"), None);
+  }
 }